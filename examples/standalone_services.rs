@@ -1,15 +1,13 @@
 // examples/standalone_services.rs
 //!Run `run --example standalone_services yourcep` to run this example
-use lagoinha;
 // optional trait for standard type conversion
 use lagoinha::services::Addressable;
 
 use std::env;
-use tokio;
 #[tokio::main]
 async fn main() {
     let args: Vec<String> = env::args().collect();
-    let mut cep: &str = "20940040".as_ref();
+    let mut cep: &str = "20940040";
     print!("{}", args.len());
     if args.len() >= 2 {
         cep = &args[1][..];
@@ -0,0 +1,279 @@
+//! Breaker stops calling a provider that keeps failing, and probes it again after a cool-down.
+//!
+//! # Example
+//! ```
+//!extern crate lagoinha;
+//!extern crate tokio;
+//!
+//!use lagoinha::breaker::BreakerPolicy;
+//!use std::time::Duration;
+//!
+//!#[tokio::main]
+//!async fn main() {
+//!    let client = lagoinha::Lagoinha::builder()
+//!        .circuit_breaker(BreakerPolicy::new(3, Duration::from_secs(60)))
+//!        .build();
+//!    let addr = client.get_address("70150903").await;
+//!    println!("{:#?}", addr);
+//!    println!("correios is {:?}", client.breaker_state("correios"));
+//!}
+//!```
+
+use crate::error::Error;
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// DEFAULT_FAILURE_THRESHOLD is the number of consecutive failures that opens a breaker, unless configured otherwise
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+
+/// DEFAULT_COOL_DOWN is the time an open breaker waits before probing the provider again, unless configured otherwise
+pub const DEFAULT_COOL_DOWN: Duration = Duration::from_secs(30);
+
+/// BreakerPolicy sets when the circuit breaker of a provider opens, and how it closes again.
+/// After `failure_threshold` consecutive failures the breaker opens, and the provider is skipped.
+/// Once the cool-down passes, the breaker is half-open: a single call at a time probes the provider,
+/// and `success_threshold` consecutive successful probes close it again. A failed probe opens it for another cool-down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BreakerPolicy {
+    failure_threshold: u32,
+    cool_down: Duration,
+    success_threshold: u32,
+}
+
+impl Default for BreakerPolicy {
+    fn default() -> Self {
+        BreakerPolicy::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_COOL_DOWN)
+    }
+}
+
+impl BreakerPolicy {
+    /// new returns a policy that opens after `failure_threshold` consecutive failures, for `cool_down`,
+    /// and closes after a single successful probe
+    pub fn new(failure_threshold: u32, cool_down: Duration) -> Self {
+        BreakerPolicy {
+            failure_threshold: failure_threshold.max(1),
+            cool_down,
+            success_threshold: 1,
+        }
+    }
+
+    /// success_threshold sets the number of consecutive successful probes needed to close a half-open breaker
+    pub fn success_threshold(mut self, successes: u32) -> Self {
+        self.success_threshold = successes.max(1);
+        self
+    }
+}
+
+/// BreakerState is the state of the circuit breaker of a provider
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakerState {
+    /// Closed indicates that the provider is called normally
+    Closed,
+    /// Open indicates that the provider is skipped, and will be probed again after `retry_in`
+    Open { retry_in: Duration },
+    /// HalfOpen indicates that the cool-down passed, and the next calls probe the provider
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed { failures: u32 },
+    Open { since: Instant },
+    HalfOpen { successes: u32, probing: bool },
+}
+
+/// Breaker is the circuit breaker of a single provider
+#[derive(Debug)]
+pub(crate) struct Breaker {
+    policy: BreakerPolicy,
+    state: Mutex<State>,
+}
+
+impl Breaker {
+    pub(crate) fn new(policy: BreakerPolicy) -> Self {
+        Breaker {
+            policy,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// state returns the current state, as seen by the next call
+    pub(crate) fn state(&self) -> BreakerState {
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => BreakerState::Closed,
+            State::Open { since } => match self.policy.cool_down.checked_sub(since.elapsed()) {
+                Some(retry_in) if !retry_in.is_zero() => BreakerState::Open { retry_in },
+                _ => BreakerState::HalfOpen,
+            },
+            State::HalfOpen { .. } => BreakerState::HalfOpen,
+        }
+    }
+
+    /// acquire asks to call the provider. It returns None if the breaker is open, or if a probe is already running.
+    pub(crate) fn acquire(&self) -> Option<Call<'_>> {
+        let mut state = self.state.lock().unwrap();
+        if let State::Open { since } = *state {
+            if since.elapsed() < self.policy.cool_down {
+                return None;
+            }
+            *state = State::HalfOpen {
+                successes: 0,
+                probing: false,
+            };
+        }
+        let probe = match &mut *state {
+            State::HalfOpen { probing: true, .. } => return None,
+            State::HalfOpen { probing, .. } => {
+                *probing = true;
+                true
+            }
+            _ => false,
+        };
+        Some(Call {
+            breaker: self,
+            probe,
+        })
+    }
+
+    fn record(&self, failed: bool) {
+        let mut state = self.state.lock().unwrap();
+        *state = match (&*state, failed) {
+            (State::Closed { failures }, true) if failures + 1 >= self.policy.failure_threshold => {
+                State::Open {
+                    since: Instant::now(),
+                }
+            }
+            (State::Closed { failures }, true) => State::Closed {
+                failures: failures + 1,
+            },
+            (State::Closed { .. }, false) => State::Closed { failures: 0 },
+            (State::HalfOpen { .. }, true) => State::Open {
+                since: Instant::now(),
+            },
+            (State::HalfOpen { successes, .. }, false)
+                if successes + 1 >= self.policy.success_threshold =>
+            {
+                State::Closed { failures: 0 }
+            }
+            (State::HalfOpen { successes, .. }, false) => State::HalfOpen {
+                successes: successes + 1,
+                probing: false,
+            },
+            // the breaker was opened by another call meanwhile
+            (State::Open { since }, _) => State::Open { since: *since },
+        };
+    }
+}
+
+/// Call is a call allowed by the breaker. If it is dropped without a result, like when the lookup is cancelled,
+/// it does not count, and a probe leaves room for the next one.
+pub(crate) struct Call<'a> {
+    breaker: &'a Breaker,
+    probe: bool,
+}
+
+impl Call<'_> {
+    /// finish records the result of the call. Only the errors that mean the provider is in trouble count as failures,
+    /// so a CEP that does not exist does not open the breaker.
+    pub(crate) fn finish<T>(mut self, result: &Result<T, Error>) {
+        self.probe = false;
+        self.breaker
+            .record(matches!(result, Err(e) if e.is_upstream_outage()));
+    }
+}
+
+impl Drop for Call<'_> {
+    fn drop(&mut self) {
+        if !self.probe {
+            return;
+        }
+        if let Ok(mut state) = self.breaker.state.lock() {
+            if let State::HalfOpen { probing, .. } = &mut *state {
+                *probing = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Breaker, BreakerPolicy, BreakerState};
+    use crate::error::{Error, Kind, Source};
+    use std::time::Duration;
+
+    fn failure() -> Result<(), Error> {
+        Err(Error {
+            source: Source::Correios,
+            kind: Kind::ServerError { code: 500 },
+        })
+    }
+
+    fn call(breaker: &Breaker, result: Result<(), Error>) {
+        breaker.acquire().unwrap().finish(&result);
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = Breaker::new(BreakerPolicy::new(3, Duration::from_secs(60)));
+        call(&breaker, failure());
+        call(&breaker, failure());
+        call(&breaker, Ok(()));
+        call(&breaker, failure());
+        call(&breaker, failure());
+        assert_eq!(breaker.state(), BreakerState::Closed);
+
+        call(&breaker, failure());
+        assert!(matches!(breaker.state(), BreakerState::Open { .. }));
+        assert!(breaker.acquire().is_none());
+    }
+
+    #[test]
+    fn not_found_does_not_count() {
+        let breaker = Breaker::new(BreakerPolicy::new(1, Duration::from_secs(60)));
+        call(
+            &breaker,
+            Err(Error {
+                source: Source::Correios,
+                kind: Kind::ClientError { code: 404 },
+            }),
+        );
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn half_open_allows_a_single_probe() {
+        let breaker = Breaker::new(BreakerPolicy::new(1, Duration::from_millis(10)));
+        call(&breaker, failure());
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+
+        let probe = breaker.acquire().unwrap();
+        assert!(breaker.acquire().is_none());
+        // a cancelled probe lets the next call probe
+        drop(probe);
+        call(&breaker, Ok(()));
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn failed_probe_opens_again() {
+        let breaker = Breaker::new(BreakerPolicy::new(1, Duration::from_millis(10)));
+        call(&breaker, failure());
+        std::thread::sleep(Duration::from_millis(20));
+        call(&breaker, failure());
+        assert!(matches!(breaker.state(), BreakerState::Open { .. }));
+    }
+
+    #[test]
+    fn success_threshold() {
+        let policy = BreakerPolicy::new(1, Duration::from_millis(10)).success_threshold(2);
+        let breaker = Breaker::new(policy);
+        call(&breaker, failure());
+        std::thread::sleep(Duration::from_millis(20));
+        call(&breaker, Ok(()));
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        call(&breaker, Ok(()));
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+}
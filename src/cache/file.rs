@@ -0,0 +1,116 @@
+//! File keeps the cache entries in a JSON file, that is read when opened and rewritten on every change.
+//! It suits small caches shared by short-lived processes, like command line jobs.
+
+use crate::cache::{cache_error, CacheBackend, Entry};
+use crate::error::Error;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// JsonFileCache is a cache backend stored in a JSON file, as an object keyed by CEP
+pub struct JsonFileCache {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<String, Entry>>,
+}
+
+impl JsonFileCache {
+    /// open loads the cache from the file at `path`. A missing file is an empty cache, created on the first insert.
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        let path = path.into();
+        let entries = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(cache_error)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(cache_error(e)),
+        };
+        Ok(JsonFileCache {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// save writes the entries to a temporary file, and moves it over the cache file,
+    /// so that a crash never leaves a partial file behind
+    fn save(&self, entries: &BTreeMap<String, Entry>) -> Result<(), Error> {
+        let contents = serde_json::to_string(entries).map_err(cache_error)?;
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, contents).map_err(cache_error)?;
+        fs::rename(&temporary, &self.path).map_err(cache_error)
+    }
+}
+
+impl CacheBackend for JsonFileCache {
+    fn get(&self, key: &str) -> Result<Option<Entry>, Error> {
+        Ok(self.entries.lock().unwrap().get(key).cloned())
+    }
+
+    fn insert(&self, key: &str, entry: Entry) -> Result<(), Error> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(key.to_owned(), entry);
+        self.save(&entries)
+    }
+
+    fn remove(&self, key: &str) -> Result<(), Error> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.remove(key).is_some() {
+            self.save(&entries)?;
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> Result<usize, Error> {
+        Ok(self.entries.lock().unwrap().len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JsonFileCache;
+    use crate::cache::{CacheBackend, Entry};
+    use crate::error::Kind;
+    use crate::services::Address;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn entry() -> Entry {
+        Entry {
+            address: Some(Address {
+                cep: "70150903".to_owned(),
+                city: "Brasília".to_owned(),
+                ..Default::default()
+            }),
+            provider: "viacep".to_owned(),
+            fetched_at: UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+        }
+    }
+
+    #[test]
+    fn entries_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+
+        let cache = JsonFileCache::open(&path).unwrap();
+        assert_eq!(cache.len(), Ok(0));
+        cache.insert("70150903", entry()).unwrap();
+        cache.insert("01001000", entry()).unwrap();
+        cache.remove("01001000").unwrap();
+        drop(cache);
+
+        let cache = JsonFileCache::open(&path).unwrap();
+        assert_eq!(cache.len(), Ok(1));
+        assert_eq!(cache.get("70150903"), Ok(Some(entry())));
+        assert_eq!(cache.get("01001000"), Ok(None));
+    }
+
+    #[test]
+    fn corrupted_files_fail_to_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        std::fs::write(&path, "not json").unwrap();
+
+        let err = JsonFileCache::open(&path).err().unwrap();
+        assert!(matches!(err.kind, Kind::CacheError { .. }));
+    }
+}
@@ -0,0 +1,129 @@
+//! Memory holds cache entries in the process memory, evicting the least recently used ones beyond its capacity.
+
+use crate::cache::{CacheBackend, Entry};
+use crate::error::Error;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<String, (Entry, u64)>,
+    /// recency maps the last use of each key to the key, the oldest first
+    recency: BTreeMap<u64, String>,
+    clock: u64,
+}
+
+impl Lru {
+    fn touch(&mut self, key: &str) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some((_, used)) = self.entries.get_mut(key) {
+            self.recency.remove(used);
+            *used = clock;
+            self.recency.insert(clock, key.to_owned());
+        }
+    }
+}
+
+/// MemoryCache is a bounded, least recently used cache of lookups
+pub struct MemoryCache {
+    capacity: usize,
+    lru: Mutex<Lru>,
+}
+
+impl MemoryCache {
+    /// new returns an empty cache that holds up to `capacity` entries
+    pub fn new(capacity: usize) -> Self {
+        MemoryCache {
+            capacity: capacity.max(1),
+            lru: Mutex::new(Lru::default()),
+        }
+    }
+}
+
+impl CacheBackend for MemoryCache {
+    /// get returns the entry of the key, marking it as recently used
+    fn get(&self, key: &str) -> Result<Option<Entry>, Error> {
+        let mut lru = self.lru.lock().unwrap();
+        lru.touch(key);
+        Ok(lru.entries.get(key).map(|(entry, _)| entry.clone()))
+    }
+
+    /// insert stores the entry of the key, evicting the least recently used entry if the cache is full
+    fn insert(&self, key: &str, entry: Entry) -> Result<(), Error> {
+        let mut lru = self.lru.lock().unwrap();
+        lru.clock += 1;
+        let clock = lru.clock;
+        if let Some((_, used)) = lru.entries.insert(key.to_owned(), (entry, clock)) {
+            lru.recency.remove(&used);
+        }
+        lru.recency.insert(clock, key.to_owned());
+
+        while lru.entries.len() > self.capacity {
+            match lru.recency.pop_first() {
+                Some((_, oldest)) => lru.entries.remove(&oldest),
+                None => break,
+            };
+        }
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<(), Error> {
+        let mut lru = self.lru.lock().unwrap();
+        if let Some((_, used)) = lru.entries.remove(key) {
+            lru.recency.remove(&used);
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> Result<usize, Error> {
+        Ok(self.lru.lock().unwrap().entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryCache;
+    use crate::cache::{CacheBackend, Entry};
+    use crate::services::Address;
+    use std::time::SystemTime;
+
+    fn entry(cep: &str) -> Entry {
+        Entry {
+            address: Some(Address {
+                cep: cep.to_owned(),
+                ..Default::default()
+            }),
+            provider: "viacep".to_owned(),
+            fetched_at: SystemTime::now(),
+        }
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let cache = MemoryCache::new(2);
+        cache.insert("1", entry("1")).unwrap();
+        cache.insert("2", entry("2")).unwrap();
+        assert!(cache.get("1").unwrap().is_some());
+        cache.insert("3", entry("3")).unwrap();
+
+        assert_eq!(cache.len(), Ok(2));
+        assert!(cache.get("2").unwrap().is_none());
+        assert_eq!(cache.get("1").unwrap().unwrap().address.unwrap().cep, "1");
+        assert!(cache.get("3").unwrap().is_some());
+    }
+
+    #[test]
+    fn insert_replaces_and_remove_drops() {
+        let cache = MemoryCache::new(2);
+        cache.insert("1", entry("1")).unwrap();
+        cache.insert("1", entry("one")).unwrap();
+        assert_eq!(cache.len(), Ok(1));
+        assert_eq!(cache.get("1").unwrap().unwrap().address.unwrap().cep, "one");
+
+        cache.remove("1").unwrap();
+        assert_eq!(cache.is_empty(), Ok(true));
+        assert!(cache.get("1").unwrap().is_none());
+    }
+}
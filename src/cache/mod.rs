@@ -0,0 +1,330 @@
+//! Cache keeps the addresses found by a client, so that repeated lookups of a CEP do not go to the network.
+//! The cache is kept in memory by default, and persistent backends are available behind cargo features:
+//! `json-cache` for cache::file::JsonFileCache, and `sqlite-cache` for cache::sqlite::SqliteCache.
+//! Other stores can be used by implementing CacheBackend.
+//!
+//! # Example
+//! ```
+//!extern crate lagoinha;
+//!extern crate tokio;
+//!
+//!use std::time::Duration;
+//!
+//!#[tokio::main]
+//!async fn main() {
+//!    let client = lagoinha::Lagoinha::builder()
+//!        .cache(10_000, Duration::from_secs(24 * 60 * 60))
+//!        .build();
+//!    let _ = client.get_address("70150-903").await;
+//!    // served from the cache, if the first lookup succeeded
+//!    let _ = client.get_address("70150903").await;
+//!    println!("{:?}", client.cache_stats());
+//!}
+//!```
+
+#[cfg(feature = "json-cache")]
+pub mod file;
+pub mod memory;
+#[cfg(feature = "sqlite-cache")]
+pub mod sqlite;
+
+use crate::error::{Error, Kind, Source};
+use crate::services::Address;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Entry is a cached lookup
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// address is the address found, or None if the CEP was confirmed not found
+    pub address: Option<Address>,
+    /// provider is the name of the provider that supplied the address, or empty if the CEP was not found
+    pub provider: String,
+    /// fetched_at is when the address, or the confirmation that it does not exist, was received
+    pub fetched_at: SystemTime,
+}
+
+/// CacheBackend is a store of cache entries, keyed by normalized CEP.
+/// The client checks the age of the entries, so backends only need to store and return them.
+/// Backend errors are taken as cache misses, and never fail a lookup.
+/// The methods are called from async code, so they are expected to be quick.
+pub trait CacheBackend: Send + Sync {
+    /// get returns the entry of the key, or None if there is none
+    fn get(&self, key: &str) -> Result<Option<Entry>, Error>;
+
+    /// insert stores the entry of the key, replacing any previous one
+    fn insert(&self, key: &str, entry: Entry) -> Result<(), Error>;
+
+    /// remove drops the entry of the key, if there is one
+    fn remove(&self, key: &str) -> Result<(), Error>;
+
+    /// len returns the number of entries in the store
+    fn len(&self) -> Result<usize, Error>;
+
+    /// is_empty indicates that the store has no entries
+    fn is_empty(&self) -> Result<bool, Error> {
+        self.len().map(|len| len == 0)
+    }
+}
+
+/// cache_error wraps the error of a cache backend in a CacheError, for use by CacheBackend implementations
+pub fn cache_error(error: impl fmt::Display) -> Error {
+    Error {
+        source: Source::LagoinhaLib,
+        kind: Kind::CacheError {
+            error: error.to_string(),
+        },
+    }
+}
+
+/// CacheStats counts how the cache of a client was used
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CacheStats {
+    /// hits is the number of lookups served by the cache right away, fresh or revalidated
+    pub hits: u64,
+    /// misses is the number of lookups that were not in the cache, or had expired, and went to the providers
+    pub misses: u64,
+    /// entries is the number of entries in the cache, including the expired ones not yet evicted
+    pub entries: usize,
+}
+
+/// CachePolicy holds the times that rule the entries of a client cache
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct CachePolicy {
+    pub ttl: Duration,
+    /// negative_ttl is the time to live of CEPs that were not found, if they are cached
+    pub negative_ttl: Option<Duration>,
+    /// revalidate is how long after expiring an entry is still served, while it is refreshed in the background
+    pub revalidate: Option<Duration>,
+    /// stale_on_error is how long after expiring an entry is still served, if every provider fails
+    pub stale_on_error: Option<Duration>,
+}
+
+/// Cached is an entry found in the cache
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Cached {
+    Fresh(Entry),
+    /// Stale is an entry past its time to live, that may still be served
+    Stale {
+        entry: Entry,
+        expired_for: Duration,
+    },
+}
+
+/// Cache is the cache of a client: a store, its policy, and its counters.
+/// Keys are normalized CEPs.
+pub(crate) struct Cache {
+    store: Arc<dyn CacheBackend>,
+    policy: CachePolicy,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    /// refreshing holds the keys being refreshed in the background
+    refreshing: Mutex<HashSet<String>>,
+}
+
+impl Cache {
+    pub(crate) fn new(store: Arc<dyn CacheBackend>, policy: CachePolicy) -> Self {
+        Cache {
+            store,
+            policy,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            refreshing: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// get returns the entry of the key, fresh or stale.
+    /// Entries that expired too long ago to be served in any case are dropped.
+    pub(crate) fn get(&self, key: &str) -> Option<Cached> {
+        let entry = self.store.get(key).ok().flatten()?;
+        let ttl = match entry.address {
+            Some(_) => self.policy.ttl,
+            None => self.policy.negative_ttl.unwrap_or_default(),
+        };
+        let expired_for = match age(&entry).checked_sub(ttl) {
+            Some(expired_for) if !expired_for.is_zero() => expired_for,
+            _ => return Some(Cached::Fresh(entry)),
+        };
+        let servable = self
+            .policy
+            .revalidate
+            .max(self.policy.stale_on_error)
+            .is_some_and(|window| expired_for <= window);
+        if servable {
+            Some(Cached::Stale { entry, expired_for })
+        } else {
+            let _ = self.store.remove(key);
+            None
+        }
+    }
+
+    /// revalidates indicates whether an entry that expired `expired_for` ago is served while it is refreshed
+    pub(crate) fn revalidates(&self, expired_for: Duration) -> bool {
+        self.policy
+            .revalidate
+            .is_some_and(|window| expired_for <= window)
+    }
+
+    /// serves_stale_on_error indicates whether an entry that expired `expired_for` ago is served when every provider fails
+    pub(crate) fn serves_stale_on_error(&self, expired_for: Duration) -> bool {
+        self.policy
+            .stale_on_error
+            .is_some_and(|window| expired_for <= window)
+    }
+
+    /// start_refresh marks the key as being refreshed. It returns false if it already was.
+    pub(crate) fn start_refresh(&self, key: &str) -> bool {
+        self.refreshing.lock().unwrap().insert(key.to_owned())
+    }
+
+    pub(crate) fn finish_refresh(&self, key: &str) {
+        self.refreshing.lock().unwrap().remove(key);
+    }
+
+    pub(crate) fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn insert(&self, key: &str, entry: Entry) {
+        let _ = self.store.insert(key, entry);
+    }
+
+    /// insert_absence records that the CEP of the key does not exist, if negative caching is enabled
+    pub(crate) fn insert_absence(&self, key: &str) {
+        if self.policy.negative_ttl.is_some() {
+            self.insert(
+                key,
+                Entry {
+                    address: None,
+                    provider: String::new(),
+                    fetched_at: SystemTime::now(),
+                },
+            );
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.store.len().unwrap_or(0),
+        }
+    }
+}
+
+/// age returns the time since the entry was fetched. Entries from the future are taken as just fetched.
+fn age(entry: &Entry) -> Duration {
+    entry.fetched_at.elapsed().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, CachePolicy, Cached, Entry};
+    use crate::cache::memory::MemoryCache;
+    use crate::services::Address;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    fn entry(fetched_at: SystemTime) -> Entry {
+        Entry {
+            address: Some(Address::default()),
+            provider: "viacep".to_owned(),
+            fetched_at,
+        }
+    }
+
+    fn policy(ttl: u64) -> CachePolicy {
+        CachePolicy {
+            ttl: Duration::from_secs(ttl),
+            negative_ttl: None,
+            revalidate: None,
+            stale_on_error: None,
+        }
+    }
+
+    fn cache(policy: CachePolicy) -> Cache {
+        Cache::new(Arc::new(MemoryCache::new(10)), policy)
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let cache = cache(policy(60));
+        cache.insert("1", entry(SystemTime::now()));
+        cache.insert("2", entry(SystemTime::now() - Duration::from_secs(120)));
+
+        assert!(matches!(cache.get("1"), Some(Cached::Fresh(_))));
+        assert!(cache.get("2").is_none());
+        assert!(cache.get("3").is_none());
+        assert_eq!(cache.stats().entries, 1);
+    }
+
+    #[test]
+    fn stale_entries_within_the_windows() {
+        let cache = cache(CachePolicy {
+            revalidate: Some(Duration::from_secs(60)),
+            stale_on_error: Some(Duration::from_secs(600)),
+            ..policy(60)
+        });
+        cache.insert("1", entry(SystemTime::now() - Duration::from_secs(90)));
+        cache.insert("2", entry(SystemTime::now() - Duration::from_secs(300)));
+        cache.insert("3", entry(SystemTime::now() - Duration::from_secs(1_000)));
+
+        match cache.get("1") {
+            Some(Cached::Stale { expired_for, .. }) => {
+                assert!(cache.revalidates(expired_for));
+                assert!(cache.serves_stale_on_error(expired_for));
+            }
+            cached => panic!("unexpected entry {:?}", cached),
+        }
+        match cache.get("2") {
+            Some(Cached::Stale { expired_for, .. }) => {
+                assert!(!cache.revalidates(expired_for));
+                assert!(cache.serves_stale_on_error(expired_for));
+            }
+            cached => panic!("unexpected entry {:?}", cached),
+        }
+        assert!(cache.get("3").is_none());
+    }
+
+    #[test]
+    fn refreshes_are_not_repeated() {
+        let cache = cache(policy(60));
+        assert!(cache.start_refresh("1"));
+        assert!(!cache.start_refresh("1"));
+        cache.finish_refresh("1");
+        assert!(cache.start_refresh("1"));
+    }
+
+    #[test]
+    fn negative_entries_have_their_own_ttl() {
+        let without = cache(policy(60));
+        without.insert_absence("123");
+        assert!(without.get("123").is_none());
+
+        let cache = cache(CachePolicy {
+            negative_ttl: Some(Duration::from_secs(10)),
+            ..policy(60)
+        });
+        cache.insert_absence("123");
+        match cache.get("123") {
+            Some(Cached::Fresh(entry)) => assert_eq!(entry.address, None),
+            cached => panic!("unexpected entry {:?}", cached),
+        }
+
+        let mut old = entry(SystemTime::now() - Duration::from_secs(30));
+        cache.insert("1", old.clone());
+        old.address = None;
+        cache.insert("2", old);
+        assert!(cache.get("1").is_some());
+        assert!(cache.get("2").is_none());
+    }
+}
@@ -0,0 +1,168 @@
+//! Sqlite keeps the cache entries in an embedded SQLite database, that can be shared by many processes.
+
+use crate::cache::{cache_error, CacheBackend, Entry};
+use crate::error::Error;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// SqliteCache is a cache backend stored in a SQLite database, in the lagoinha_cache table.
+/// The address is stored as JSON, or null for a CEP that was not found,
+/// next to the provider and the fetch time in milliseconds since the Unix epoch.
+pub struct SqliteCache {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteCache {
+    /// open opens or creates the database at `path`, and creates the cache table if needed
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        SqliteCache::with_connection(Connection::open(path).map_err(cache_error)?)
+    }
+
+    /// in_memory creates a database that only lives as long as the cache
+    pub fn in_memory() -> Result<Self, Error> {
+        SqliteCache::with_connection(Connection::open_in_memory().map_err(cache_error)?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self, Error> {
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS lagoinha_cache (
+                    cep TEXT PRIMARY KEY,
+                    address TEXT NOT NULL,
+                    provider TEXT NOT NULL,
+                    fetched_at INTEGER NOT NULL
+                )",
+                [],
+            )
+            .map_err(cache_error)?;
+        Ok(SqliteCache {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+impl CacheBackend for SqliteCache {
+    fn get(&self, key: &str) -> Result<Option<Entry>, Error> {
+        let connection = self.connection.lock().unwrap();
+        let row = connection
+            .query_row(
+                "SELECT address, provider, fetched_at FROM lagoinha_cache WHERE cep = ?1",
+                params![key],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, i64>(2)?,
+                    ))
+                },
+            )
+            .optional()
+            .map_err(cache_error)?;
+
+        match row {
+            Some((address, provider, fetched_at)) => Ok(Some(Entry {
+                address: serde_json::from_str(&address).map_err(cache_error)?,
+                provider,
+                fetched_at: UNIX_EPOCH + Duration::from_millis(fetched_at.max(0) as u64),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    fn insert(&self, key: &str, entry: Entry) -> Result<(), Error> {
+        let address = serde_json::to_string(&entry.address).map_err(cache_error)?;
+        let fetched_at = entry
+            .fetched_at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as i64);
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO lagoinha_cache (cep, address, provider, fetched_at)
+                VALUES (?1, ?2, ?3, ?4)",
+                params![key, address, entry.provider, fetched_at],
+            )
+            .map_err(cache_error)?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<(), Error> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM lagoinha_cache WHERE cep = ?1", params![key])
+            .map_err(cache_error)?;
+        Ok(())
+    }
+
+    fn len(&self) -> Result<usize, Error> {
+        self.connection
+            .lock()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM lagoinha_cache", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .map(|count| count as usize)
+            .map_err(cache_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SqliteCache;
+    use crate::cache::{CacheBackend, Entry};
+    use crate::services::Address;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn entry(provider: &str) -> Entry {
+        Entry {
+            address: Some(Address {
+                cep: "70150903".to_owned(),
+                city: "Brasília".to_owned(),
+                ibge: "5300108".to_owned(),
+                ..Default::default()
+            }),
+            provider: provider.to_owned(),
+            fetched_at: UNIX_EPOCH + Duration::from_millis(1_600_000_000_123),
+        }
+    }
+
+    #[test]
+    fn entries_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.sqlite");
+
+        let cache = SqliteCache::open(&path).unwrap();
+        cache.insert("70150903", entry("viacep")).unwrap();
+        cache.insert("70150903", entry("cepla")).unwrap();
+        cache.insert("01001000", entry("viacep")).unwrap();
+        cache.remove("01001000").unwrap();
+        drop(cache);
+
+        let cache = SqliteCache::open(&path).unwrap();
+        assert_eq!(cache.len(), Ok(1));
+        assert_eq!(cache.get("70150903"), Ok(Some(entry("cepla"))));
+        assert_eq!(cache.get("01001000"), Ok(None));
+    }
+
+    #[test]
+    fn negative_entries() {
+        let cache = SqliteCache::in_memory().unwrap();
+        let mut not_found = entry("");
+        not_found.address = None;
+        cache.insert("123", not_found.clone()).unwrap();
+        assert_eq!(cache.get("123"), Ok(Some(not_found)));
+    }
+
+    #[test]
+    fn in_memory() {
+        let cache = SqliteCache::in_memory().unwrap();
+        assert_eq!(cache.is_empty(), Ok(true));
+        cache.insert("70150903", entry("viacep")).unwrap();
+        assert_eq!(cache.is_empty(), Ok(false));
+    }
+}
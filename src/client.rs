@@ -0,0 +1,1894 @@
+//! Client holds a configurable pool of providers, and runs concurrent requests to all of them.
+//!
+//! # Example
+//! ```
+//!extern crate lagoinha;
+//!extern crate tokio;
+//!
+//!use lagoinha::client::{Lagoinha, Service};
+//!
+//!#[tokio::main]
+//!async fn main() {
+//!    let client = Lagoinha::builder().disable(Service::Correios).build();
+//!    let addr = client.get_address("70150903").await;
+//!    println!("{:#?}", addr);
+//!}
+//!```
+
+use crate::breaker::{Breaker, BreakerPolicy, BreakerState};
+use crate::cache::memory::MemoryCache;
+use crate::cache::{Cache, CacheBackend, CachePolicy, CacheStats, Cached, Entry};
+use crate::consensus::{self, Consensus, Quorum};
+use crate::error::Error;
+use crate::error::{self, Kind};
+use crate::merge::{self, Merged};
+use crate::race;
+use crate::ratelimit::{Exceeded, Limiter, RateLimit};
+use crate::report::{Lookup, Recorder};
+use crate::retry::RetryPolicy;
+use crate::services::{self, normalize_cep, Address, Capabilities, CepProvider};
+use crate::stats::{self, ProviderStats, Stats};
+
+use async_lock::Semaphore;
+use async_std::future::timeout;
+use async_std::task;
+use futures::future::{BoxFuture, FutureExt, Shared, WeakShared};
+use futures::stream::{self, BoxStream, StreamExt};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// DEFAULT_TIMEOUT is the time each provider has to answer, unless configured otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// DEFAULT_BATCH_CONCURRENCY is the number of lookups a batch runs at once, if the client has no concurrency limit
+pub const DEFAULT_BATCH_CONCURRENCY: usize = 16;
+
+/// Service represents one of the services built into the library
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Service {
+    Viacep,
+    Correios,
+    Cepla,
+}
+
+impl Service {
+    /// provider returns the CepProvider implementation of this service
+    pub fn provider(self) -> Arc<dyn CepProvider> {
+        match self {
+            Service::Viacep => Arc::new(services::viacep::ViacepProvider),
+            Service::Correios => Arc::new(services::correios::CorreiosProvider),
+            Service::Cepla => Arc::new(services::cepla::CeplaProvider),
+        }
+    }
+
+    /// name returns the name of this service in the pool
+    pub fn name(self) -> &'static str {
+        match self {
+            Service::Viacep => "viacep",
+            Service::Correios => "correios",
+            Service::Cepla => "cepla",
+        }
+    }
+}
+
+/// Strategy sets how get_address uses the providers in the pool
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Strategy {
+    /// Race calls every provider at once, and returns the first successful answer. It is the default.
+    #[default]
+    Race,
+    /// Fallback tries the providers in the order of the pool, or in the order of their ranking if adaptive ranking is enabled,
+    /// moving to the next one when a provider fails.
+    /// With a hedge delay, the next provider is also started if no provider answered within the delay.
+    Fallback { hedge: Option<Duration> },
+}
+
+/// Response is a successful answer from a provider, as compared by the consensus and merge modes
+pub(crate) struct Response {
+    /// index is the position of the provider in the pool
+    pub index: usize,
+    pub provider: String,
+    pub capabilities: Capabilities,
+    pub address: Address,
+}
+
+/// Slot holds a provider of the pool, with its own settings
+struct Slot {
+    provider: Arc<dyn CepProvider>,
+    timeout: Duration,
+    /// permits limits the concurrent requests to the provider, if a limit was set
+    permits: Option<Semaphore>,
+    retry: RetryPolicy,
+    /// breaker skips the provider while it keeps failing, if a circuit breaker was set
+    breaker: Option<Breaker>,
+    /// limiter keeps the requests to the provider within its budget, if a rate limit was set
+    limiter: Option<Limiter>,
+    stats: Stats,
+}
+
+/// InFlight is a fetch shared by every concurrent lookup of a CEP
+type InFlight = WeakShared<BoxFuture<'static, Result<Lookup, Error>>>;
+
+struct Inner {
+    providers: Vec<Slot>,
+    deadline: Option<Duration>,
+    strategy: Strategy,
+    adaptive_ranking: bool,
+    batch_concurrency: usize,
+    cache: Option<Cache>,
+    /// in_flight holds the fetches running for each normalized CEP.
+    /// They are weak, so that a fetch is cancelled once every lookup waiting for it is dropped.
+    in_flight: Mutex<HashMap<String, InFlight>>,
+}
+
+/// Lagoinha is a reusable client that keeps its services configuration for as long as it lives.
+/// It is cheap to clone, and all clones share the same configuration.
+#[derive(Clone)]
+pub struct Lagoinha {
+    inner: Arc<Inner>,
+}
+
+impl Default for Lagoinha {
+    /// default builds a client with all the built-in services enabled
+    fn default() -> Self {
+        Lagoinha::builder().build()
+    }
+}
+
+impl Lagoinha {
+    /// builder returns a LagoinhaBuilder with all the built-in services enabled
+    pub fn builder() -> LagoinhaBuilder {
+        LagoinhaBuilder::default()
+    }
+
+    /// providers returns the names of the providers in the pool, in the order they were added
+    pub fn providers(&self) -> Vec<String> {
+        self.inner
+            .providers
+            .iter()
+            .map(|slot| slot.provider.name().to_owned())
+            .collect()
+    }
+
+    /// get_address requests the address related to the provided `cep` from the providers in the pool, following the client Strategy,
+    /// and returns the first successful result. The requests still running are cancelled.
+    /// With the default Strategy::Race, all the providers are called concurrently.
+    /// If every provider fails, the returned error lists all their errors.
+    /// Providers that do not answer within their timeout fail with a Timeout error,
+    /// and if the client has a deadline, the whole lookup fails with DeadlineExceeded once it passes.
+    ///
+    /// # Arguments
+    ///
+    /// * `cep` - A str pointer slice that holds the Brazilian postal code.
+    ///
+    pub async fn get_address(&self, cep: &str) -> Result<Address, Error> {
+        self.lookup(cep).await.map(|lookup| lookup.address)
+    }
+
+    /// lookup works as get_address, but also reports which provider supplied the address,
+    /// how long it took, and the outcome of every other provider attempt.
+    /// Concurrent lookups of the same CEP share a single race against the providers, and get the same result.
+    ///
+    /// # Arguments
+    ///
+    /// * `cep` - A str pointer slice that holds the Brazilian postal code.
+    ///
+    pub async fn lookup(&self, cep: &str) -> Result<Lookup, Error> {
+        let cache = match &self.inner.cache {
+            Some(cache) => cache,
+            None => return self.coalesced_fetch(cep).await,
+        };
+        let key = normalize_cep(cep);
+        let start = Instant::now();
+        let stale = match cache.get(&key) {
+            Some(Cached::Fresh(entry)) => {
+                cache.hit();
+                return self.cached(entry, start, false);
+            }
+            Some(Cached::Stale { entry, expired_for }) if cache.revalidates(expired_for) => {
+                cache.hit();
+                self.refresh(cache, cep, key);
+                return self.cached(entry, start, true);
+            }
+            Some(Cached::Stale { entry, expired_for })
+                if cache.serves_stale_on_error(expired_for) =>
+            {
+                Some(entry)
+            }
+            _ => None,
+        };
+
+        cache.miss();
+        match (self.coalesced_fetch(cep).await, stale) {
+            (Err(_), Some(entry)) => self.cached(entry, start, true),
+            (result, _) => result,
+        }
+    }
+
+    /// cached returns the lookup of a cache entry. A CEP that was not found fails with NotFound.
+    fn cached(&self, entry: Entry, start: Instant, stale: bool) -> Result<Lookup, Error> {
+        let provider = entry.provider;
+        let address = entry.address.ok_or(Error {
+            source: error::Source::LagoinhaLib,
+            kind: Kind::NotFound,
+        })?;
+        let source = self
+            .inner
+            .providers
+            .iter()
+            .find(|slot| slot.provider.name() == provider)
+            .map_or(error::Source::LagoinhaLib, |slot| slot.provider.source());
+        Ok(Lookup {
+            address,
+            provider,
+            source,
+            latency: start.elapsed(),
+            attempts: vec![],
+            cached: true,
+            stale,
+        })
+    }
+
+    /// refresh looks the CEP up again in the background, to update its cache entry.
+    /// Only one refresh of a CEP runs at a time.
+    fn refresh(&self, cache: &Cache, cep: &str, key: String) {
+        if !cache.start_refresh(&key) {
+            return;
+        }
+        let client = self.clone();
+        let cep = cep.to_owned();
+        task::spawn(async move {
+            let _ = client.coalesced_fetch(&cep).await;
+            if let Some(cache) = &client.inner.cache {
+                cache.finish_refresh(&key);
+            }
+        });
+    }
+
+    /// coalesced_fetch joins the fetch of the same normalized CEP that is already running, or starts a new one.
+    /// Every lookup that joins a fetch gets the same result.
+    async fn coalesced_fetch(&self, cep: &str) -> Result<Lookup, Error> {
+        let key = normalize_cep(cep);
+        let fetch = {
+            let mut in_flight = self.inner.in_flight.lock().unwrap();
+            match in_flight.get(&key).and_then(WeakShared::upgrade) {
+                Some(fetch) => fetch,
+                None => {
+                    // drops the fetches cancelled by all of their lookups
+                    in_flight.retain(|_, fetch| fetch.upgrade().is_some());
+                    let fetch = self.shared_fetch(cep, key.clone());
+                    if let Some(weak) = fetch.downgrade() {
+                        in_flight.insert(key, weak);
+                    }
+                    fetch
+                }
+            }
+        };
+        fetch.await
+    }
+
+    fn shared_fetch(
+        &self,
+        cep: &str,
+        key: String,
+    ) -> Shared<BoxFuture<'static, Result<Lookup, Error>>> {
+        let client = self.clone();
+        let cep = cep.to_owned();
+        async move {
+            let result = client.fetch(&cep).await;
+            client.inner.in_flight.lock().unwrap().remove(&key);
+            result
+        }
+        .boxed()
+        .shared()
+    }
+
+    /// fetch runs a lookup against the providers, following the client Strategy, and stores the result in the cache
+    async fn fetch(&self, cep: &str) -> Result<Lookup, Error> {
+        let recorder = Recorder::default();
+        let providers = &self.inner.providers;
+        let order;
+        let race = match self.inner.strategy {
+            Strategy::Race => {
+                // collected, so that the future does not hold the closure, and stays Send
+                let requests: Vec<_> = providers
+                    .iter()
+                    .map(|slot| self.attempt(slot, cep, &recorder))
+                    .collect();
+                race::first_success(requests).left_future()
+            }
+            Strategy::Fallback { hedge } => {
+                order = self.order();
+                let start = |i: usize| self.attempt(&providers[order[i]], cep, &recorder);
+                race::fallback(providers.len(), start, hedge).right_future()
+            }
+        };
+
+        let result = match self.inner.deadline {
+            Some(deadline) => timeout(deadline, race).await.map_err(|_| Error {
+                source: error::Source::LagoinhaLib,
+                kind: Kind::DeadlineExceeded { after: deadline },
+            })?,
+            None => race.await,
+        };
+        let cache = self.inner.cache.as_ref();
+        let (index, address) = result.map_err(|errors| {
+            if !not_found(&errors) {
+                return all_services_error(&errors);
+            }
+            if let Some(cache) = cache {
+                cache.insert_absence(&normalize_cep(cep));
+            }
+            Error {
+                source: error::Source::LagoinhaLib,
+                kind: Kind::NotFound,
+            }
+        })?;
+
+        let attempts = recorder.into_attempts();
+        let winner = &attempts[index];
+        if let Some(cache) = cache {
+            cache.insert(
+                &normalize_cep(cep),
+                Entry {
+                    address: Some(address.clone()),
+                    provider: winner.provider.clone(),
+                    fetched_at: SystemTime::now(),
+                },
+            );
+        }
+        Ok(Lookup {
+            address,
+            provider: winner.provider.clone(),
+            source: winner.source.clone(),
+            latency: winner.latency,
+            attempts,
+            cached: false,
+            stale: false,
+        })
+    }
+
+    /// batch looks up many CEPs, running as many lookups at once as the client concurrency allows.
+    /// CEPs that only differ in formatting, like "70150-903" and "70150903", are looked up once.
+    /// The results are keyed by the CEPs as they were given.
+    pub async fn batch<I, S>(&self, ceps: I) -> HashMap<String, Result<Address, Error>>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.batch_stream(ceps).collect().await
+    }
+
+    /// batch_stream works as batch, but yields the result of each CEP as soon as its lookup completes
+    pub fn batch_stream<I, S>(
+        &self,
+        ceps: I,
+    ) -> BoxStream<'static, (String, Result<Address, Error>)>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        // inputs are grouped by normalized CEP, in the order they first appear
+        let mut groups: Vec<Vec<String>> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for cep in ceps {
+            let cep = cep.into();
+            match positions.get(&normalize_cep(&cep)) {
+                Some(&i) if groups[i].contains(&cep) => (),
+                Some(&i) => groups[i].push(cep),
+                None => {
+                    positions.insert(normalize_cep(&cep), groups.len());
+                    groups.push(vec![cep]);
+                }
+            }
+        }
+
+        let client = self.clone();
+        stream::iter(groups)
+            .map(move |inputs| {
+                let client = client.clone();
+                async move {
+                    let result = client.get_address(&inputs[0]).await;
+                    (inputs, result)
+                }
+            })
+            .buffer_unordered(self.inner.batch_concurrency)
+            .flat_map(|(inputs, result)| {
+                stream::iter(inputs.into_iter().map(move |cep| (cep, result.clone())))
+            })
+            .boxed()
+    }
+
+    /// consensus requests the address related to the provided `cep` from the providers in the pool, and compares their answers field by field.
+    /// It waits for the number of answers required by the quorum, or for every provider within the client deadline,
+    /// and returns the majority answer with a report of the fields where the providers disagreed.
+    ///
+    /// # Arguments
+    ///
+    /// * `cep` - A str pointer slice that holds the Brazilian postal code.
+    /// * `quorum` - How many providers must answer before comparing. `Quorum::All` accepts fewer answers if the deadline passes.
+    ///
+    pub async fn consensus(&self, cep: &str, quorum: Quorum) -> Result<Consensus, Error> {
+        let (wanted, required) = match quorum {
+            Quorum::AtLeast(n) => (n.max(1), n.max(1)),
+            Quorum::All => (self.inner.providers.len(), 1),
+        };
+
+        let responses = self.gather(cep, wanted).await?;
+        if responses.len() < required {
+            return Err(Error {
+                source: error::Source::LagoinhaLib,
+                kind: Kind::QuorumNotReached {
+                    required,
+                    answered: responses.len(),
+                },
+            });
+        }
+        consensus::vote(&responses).ok_or(Error {
+            source: error::Source::LagoinhaLib,
+            kind: Kind::UnexpectedLibraryError,
+        })
+    }
+
+    /// merge requests the address related to the provided `cep` from every provider in the pool, within the client deadline,
+    /// and fills each field from the best provider that returned it.
+    /// Providers are ranked by their order in the pool, and the ones that declare support for a field are preferred.
+    /// The result records which provider each field came from.
+    ///
+    /// # Arguments
+    ///
+    /// * `cep` - A str pointer slice that holds the Brazilian postal code.
+    ///
+    pub async fn merge(&self, cep: &str) -> Result<Merged, Error> {
+        let responses = self.gather(cep, self.inner.providers.len()).await?;
+        merge::merge(&responses).ok_or(Error {
+            source: error::Source::LagoinhaLib,
+            kind: Kind::UnexpectedLibraryError,
+        })
+    }
+
+    /// gather runs concurrent calls to the providers in the pool until `wanted` of them answer,
+    /// or until the client deadline passes. It fails if no provider answered.
+    async fn gather(&self, cep: &str, wanted: usize) -> Result<Vec<Response>, Error> {
+        let providers = &self.inner.providers;
+        let requests: Vec<_> = providers
+            .iter()
+            .enumerate()
+            .map(|(index, slot)| async move {
+                self.lookup_provider(slot, cep)
+                    .await
+                    .map(|address| Response {
+                        index,
+                        provider: slot.provider.name().to_owned(),
+                        capabilities: slot.provider.capabilities(),
+                        address,
+                    })
+            })
+            .collect();
+        let (responses, errors) = race::collect(requests, wanted, self.inner.deadline).await;
+
+        if responses.is_empty() {
+            return Err(match self.inner.deadline {
+                Some(after) if errors.len() < providers.len() => Error {
+                    source: error::Source::LagoinhaLib,
+                    kind: Kind::DeadlineExceeded { after },
+                },
+                _ => all_services_error(&errors),
+            });
+        }
+        Ok(responses)
+    }
+
+    /// timeout returns the request timeout used for the named provider, or None if it is not in the pool
+    pub fn timeout(&self, provider: &str) -> Option<Duration> {
+        self.inner
+            .providers
+            .iter()
+            .find(|slot| slot.provider.name() == provider)
+            .map(|slot| slot.timeout)
+    }
+
+    /// breaker_state returns the state of the circuit breaker of the named provider, or None if it is not in the pool.
+    /// Providers without a circuit breaker are always Closed.
+    pub fn breaker_state(&self, provider: &str) -> Option<BreakerState> {
+        self.inner
+            .providers
+            .iter()
+            .find(|slot| slot.provider.name() == provider)
+            .map(|slot| {
+                slot.breaker
+                    .as_ref()
+                    .map_or(BreakerState::Closed, Breaker::state)
+            })
+    }
+
+    /// cache_stats returns the hit and miss counters of the client cache, or None if the client has no cache
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache.as_ref().map(Cache::stats)
+    }
+
+    /// stats returns the rolling statistics of each provider in the pool, in the order they were added
+    pub fn stats(&self) -> Vec<ProviderStats> {
+        self.inner
+            .providers
+            .iter()
+            .map(|slot| slot.stats.snapshot(slot.provider.name()))
+            .collect()
+    }
+
+    /// ranking returns the names of the providers in the order Strategy::Fallback calls them:
+    /// the order of the pool, or the best ranked first if adaptive ranking is enabled
+    pub fn ranking(&self) -> Vec<String> {
+        self.order()
+            .into_iter()
+            .map(|i| self.inner.providers[i].provider.name().to_owned())
+            .collect()
+    }
+
+    /// order returns the positions of the providers in the order Strategy::Fallback calls them
+    fn order(&self) -> Vec<usize> {
+        if self.inner.adaptive_ranking {
+            stats::rank(&self.stats())
+        } else {
+            (0..self.inner.providers.len()).collect()
+        }
+    }
+
+    /// attempt calls a provider, recording the attempt. It returns the index of the attempt with the address.
+    async fn attempt(
+        &self,
+        slot: &Slot,
+        cep: &str,
+        recorder: &Recorder,
+    ) -> Result<(usize, Address), Error> {
+        let guard = recorder.start(slot.provider.as_ref());
+        let result = self.lookup_provider(slot, cep).await;
+        let index = guard.finish(&result);
+        result.map(|address| (index, address))
+    }
+
+    /// lookup_provider calls a provider, retrying transient failures as allowed by its RetryPolicy
+    async fn lookup_provider(&self, slot: &Slot, cep: &str) -> Result<Address, Error> {
+        let mut attempts = 1;
+        loop {
+            match self.call_provider(slot, cep).await {
+                Err(e) if slot.retry.should_retry(attempts, &e) => {
+                    task::sleep(slot.retry.delay(attempts, &e)).await;
+                    attempts += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// call_provider calls a provider once its circuit breaker, rate limit and concurrency limit allow, and within its timeout
+    async fn call_provider(&self, slot: &Slot, cep: &str) -> Result<Address, Error> {
+        let provider = &slot.provider;
+        let call = match &slot.breaker {
+            Some(breaker) => Some(breaker.acquire().ok_or_else(|| Error {
+                source: provider.source(),
+                kind: Kind::CircuitOpen {
+                    provider: provider.name().to_owned(),
+                },
+            })?),
+            None => None,
+        };
+        if let Some(limiter) = &slot.limiter {
+            limiter.acquire().await.map_err(|exceeded| Error {
+                source: provider.source(),
+                kind: match exceeded {
+                    Exceeded::Rate => Kind::RateLimited {
+                        provider: provider.name().to_owned(),
+                    },
+                    Exceeded::Quota(quota) => Kind::QuotaExhausted {
+                        provider: provider.name().to_owned(),
+                        quota,
+                    },
+                },
+            })?;
+        }
+        let _permit = match &slot.permits {
+            Some(permits) => Some(permits.acquire().await),
+            None => None,
+        };
+        let after = slot.timeout;
+        let start = Instant::now();
+        let result = match timeout(after, provider.lookup(cep)).await {
+            Ok(result) => result,
+            Err(_) => Err(Error {
+                source: provider.source(),
+                kind: Kind::Timeout {
+                    provider: provider.name().to_owned(),
+                    after,
+                },
+            }),
+        };
+        let failed = matches!(&result, Err(e) if e.is_upstream_outage());
+        slot.stats.record(start.elapsed(), failed);
+        if let Some(call) = call {
+            call.finish(&result);
+        }
+        result
+    }
+}
+
+/// LagoinhaBuilder configures which providers are used by a Lagoinha client
+pub struct LagoinhaBuilder {
+    providers: Vec<Arc<dyn CepProvider>>,
+    timeout: Duration,
+    provider_timeouts: HashMap<String, Duration>,
+    concurrency: Option<usize>,
+    provider_concurrency: HashMap<String, usize>,
+    retry: RetryPolicy,
+    provider_retries: HashMap<String, RetryPolicy>,
+    breaker: Option<BreakerPolicy>,
+    provider_breakers: HashMap<String, BreakerPolicy>,
+    rate_limit: Option<RateLimit>,
+    provider_rate_limits: HashMap<String, RateLimit>,
+    cache: Option<(Arc<dyn CacheBackend>, Duration)>,
+    negative_ttl: Option<Duration>,
+    revalidate: Option<Duration>,
+    stale_on_error: Option<Duration>,
+    deadline: Option<Duration>,
+    strategy: Strategy,
+    adaptive_ranking: bool,
+}
+
+impl Default for LagoinhaBuilder {
+    fn default() -> Self {
+        LagoinhaBuilder {
+            providers: vec![
+                Service::Viacep.provider(),
+                Service::Correios.provider(),
+                Service::Cepla.provider(),
+            ],
+            timeout: DEFAULT_TIMEOUT,
+            provider_timeouts: HashMap::new(),
+            concurrency: None,
+            provider_concurrency: HashMap::new(),
+            retry: RetryPolicy::default(),
+            provider_retries: HashMap::new(),
+            breaker: None,
+            provider_breakers: HashMap::new(),
+            rate_limit: None,
+            provider_rate_limits: HashMap::new(),
+            cache: None,
+            negative_ttl: None,
+            revalidate: None,
+            stale_on_error: None,
+            deadline: None,
+            strategy: Strategy::default(),
+            adaptive_ranking: false,
+        }
+    }
+}
+
+impl LagoinhaBuilder {
+    /// enable adds a built-in service to the pool. Enabling a service that is already in the pool has no effect.
+    pub fn enable(mut self, service: Service) -> Self {
+        if !self.providers.iter().any(|p| p.name() == service.name()) {
+            self.providers.push(service.provider());
+        }
+        self
+    }
+
+    /// disable removes a built-in service from the pool
+    pub fn disable(mut self, service: Service) -> Self {
+        self.providers.retain(|p| p.name() != service.name());
+        self
+    }
+
+    /// provider adds a custom CepProvider to the pool
+    pub fn provider<P>(self, provider: P) -> Self
+    where
+        P: CepProvider + 'static,
+    {
+        self.provider_arc(Arc::new(provider))
+    }
+
+    /// provider_arc adds a CepProvider trait object to the pool
+    pub fn provider_arc(mut self, provider: Arc<dyn CepProvider>) -> Self {
+        self.providers.push(provider);
+        self
+    }
+
+    /// priority reorders the pool: the named providers come first, in the given order, followed by the others in their current order.
+    /// The order of the pool is the order used by Strategy::Fallback.
+    pub fn priority(mut self, names: &[&str]) -> Self {
+        let rank = |provider: &Arc<dyn CepProvider>| {
+            names
+                .iter()
+                .position(|name| *name == provider.name())
+                .unwrap_or(names.len())
+        };
+        self.providers.sort_by_key(rank);
+        self
+    }
+
+    /// strategy sets how get_address uses the providers in the pool. It defaults to Strategy::Race.
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// adaptive_ranking makes Strategy::Fallback, with or without hedging, call the providers ordered by their recent statistics
+    /// instead of the order of the pool: the fastest and most reliable first. Providers without statistics yet are called first.
+    /// It is disabled by default.
+    pub fn adaptive_ranking(mut self, enabled: bool) -> Self {
+        self.adaptive_ranking = enabled;
+        self
+    }
+
+    /// timeout sets the time each provider has to answer a request. It defaults to DEFAULT_TIMEOUT.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// provider_timeout sets the time the named provider has to answer a request, overriding the client timeout
+    pub fn provider_timeout(mut self, provider: &str, timeout: Duration) -> Self {
+        self.provider_timeouts.insert(provider.to_owned(), timeout);
+        self
+    }
+
+    /// concurrency limits the number of concurrent requests to each provider, across every lookup made with the client.
+    /// It is also the number of lookups a batch runs at once. There is no limit by default.
+    pub fn concurrency(mut self, limit: usize) -> Self {
+        self.concurrency = Some(limit.max(1));
+        self
+    }
+
+    /// provider_concurrency limits the number of concurrent requests to the named provider, overriding the client concurrency
+    pub fn provider_concurrency(mut self, provider: &str, limit: usize) -> Self {
+        self.provider_concurrency
+            .insert(provider.to_owned(), limit.max(1));
+        self
+    }
+
+    /// retry sets how each provider call is retried after a transient failure. Calls are not retried by default.
+    /// Each retry gets the full provider timeout, while the client deadline bounds the whole lookup.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// provider_retry sets how calls to the named provider are retried, overriding the client retry policy
+    pub fn provider_retry(mut self, provider: &str, policy: RetryPolicy) -> Self {
+        self.provider_retries.insert(provider.to_owned(), policy);
+        self
+    }
+
+    /// circuit_breaker gives each provider its own circuit breaker, so that providers that keep failing are skipped
+    /// until their cool-down passes. There is no circuit breaker by default.
+    pub fn circuit_breaker(mut self, policy: BreakerPolicy) -> Self {
+        self.breaker = Some(policy);
+        self
+    }
+
+    /// provider_circuit_breaker sets the circuit breaker of the named provider, overriding the client circuit breaker
+    pub fn provider_circuit_breaker(mut self, provider: &str, policy: BreakerPolicy) -> Self {
+        self.provider_breakers.insert(provider.to_owned(), policy);
+        self
+    }
+
+    /// rate_limit gives each provider its own request budget, shared by every lookup made with the client.
+    /// There is no rate limit by default.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// provider_rate_limit sets the request budget of the named provider, overriding the client rate limit
+    pub fn provider_rate_limit(mut self, provider: &str, limit: RateLimit) -> Self {
+        self.provider_rate_limits.insert(provider.to_owned(), limit);
+        self
+    }
+
+    /// cache keeps up to `capacity` addresses in memory for `ttl`, evicting the least recently used ones.
+    /// get_address, lookup and batch are served from the cache, while consensus and merge always call the providers.
+    /// CEPs that only differ in formatting share an entry. There is no cache by default.
+    pub fn cache(self, capacity: usize, ttl: Duration) -> Self {
+        self.cache_backend(MemoryCache::new(capacity), ttl)
+    }
+
+    /// cache_backend keeps the addresses in the given store for `ttl`, like cache does in memory.
+    /// Persistent backends let the cache outlive the process.
+    pub fn cache_backend<B>(mut self, backend: B, ttl: Duration) -> Self
+    where
+        B: CacheBackend + 'static,
+    {
+        self.cache = Some((Arc::new(backend), ttl));
+        self
+    }
+
+    /// negative_cache also caches the CEPs not found, for `ttl`, which is usually shorter than the cache TTL.
+    /// These are the lookups that fail with NotFound, and so do the cached ones. It requires cache or cache_backend, and is disabled by default.
+    pub fn negative_cache(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = Some(ttl);
+        self
+    }
+
+    /// stale_while_revalidate serves cache entries that expired less than `window` ago right away, marked as stale,
+    /// and refreshes them in the background. It requires cache or cache_backend, and is disabled by default.
+    pub fn stale_while_revalidate(mut self, window: Duration) -> Self {
+        self.revalidate = Some(window);
+        self
+    }
+
+    /// serve_stale_on_error serves cache entries that expired less than `window` ago, marked as stale,
+    /// when every provider fails. It requires cache or cache_backend, and is disabled by default.
+    pub fn serve_stale_on_error(mut self, window: Duration) -> Self {
+        self.stale_on_error = Some(window);
+        self
+    }
+
+    /// deadline sets the maximum duration of a whole lookup, including every provider. There is no deadline by default.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// build creates the Lagoinha client
+    pub fn build(self) -> Lagoinha {
+        let timeout = self.timeout;
+        let provider_timeouts = self.provider_timeouts;
+        let concurrency = self.concurrency;
+        let provider_concurrency = self.provider_concurrency;
+        let retry = self.retry;
+        let provider_retries = self.provider_retries;
+        let breaker = self.breaker;
+        let provider_breakers = self.provider_breakers;
+        let rate_limit = self.rate_limit;
+        let negative_ttl = self.negative_ttl;
+        let revalidate = self.revalidate;
+        let stale_on_error = self.stale_on_error;
+        let provider_rate_limits = self.provider_rate_limits;
+        let providers = self
+            .providers
+            .into_iter()
+            .map(|provider| {
+                let name = provider.name();
+                Slot {
+                    timeout: provider_timeouts.get(name).copied().unwrap_or(timeout),
+                    permits: provider_concurrency
+                        .get(name)
+                        .copied()
+                        .or(concurrency)
+                        .map(Semaphore::new),
+                    retry: provider_retries.get(name).copied().unwrap_or(retry),
+                    breaker: provider_breakers
+                        .get(name)
+                        .copied()
+                        .or(breaker)
+                        .map(Breaker::new),
+                    limiter: provider_rate_limits
+                        .get(name)
+                        .copied()
+                        .or(rate_limit)
+                        .map(Limiter::new),
+                    stats: Stats::default(),
+                    provider,
+                }
+            })
+            .collect();
+
+        Lagoinha {
+            inner: Arc::new(Inner {
+                providers,
+                deadline: self.deadline,
+                strategy: self.strategy,
+                adaptive_ranking: self.adaptive_ranking,
+                batch_concurrency: concurrency.unwrap_or(DEFAULT_BATCH_CONCURRENCY),
+                cache: self.cache.map(|(backend, ttl)| {
+                    let policy = CachePolicy {
+                        ttl,
+                        negative_ttl,
+                        revalidate,
+                        stale_on_error,
+                    };
+                    Cache::new(backend, policy)
+                }),
+                in_flight: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+}
+
+/// not_found indicates whether the errors of a failed lookup agree that the CEP does not exist:
+/// at least one provider reported it as not found, and none rejected it as malformed.
+/// The other providers may have failed for unrelated reasons, like an outage.
+fn not_found(errors: &[Error]) -> bool {
+    errors.iter().any(Error::is_not_found) && !errors.iter().any(Error::is_input_error)
+}
+
+fn all_services_error(error_list: &[Error]) -> Error {
+    Error {
+        source: error::Source::LagoinhaLib,
+        kind: Kind::AllServicesReturnedErrors {
+            errors: error_list.to_vec(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lagoinha, Service, Strategy};
+    use crate::breaker::{BreakerPolicy, BreakerState};
+    use crate::consensus::Quorum;
+    use crate::error::{Error, Kind, Source};
+    use crate::ratelimit::RateLimit;
+    use crate::report::Outcome;
+    use crate::retry::RetryPolicy;
+    use crate::services::{Address, CepProvider};
+    use futures::future::{BoxFuture, FutureExt};
+    use futures::stream::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    /// Fake is a provider that answers without network calls after its delay: an address, or an error
+    struct Fake {
+        name: &'static str,
+        delay: Duration,
+        outcome: Result<(), Kind>,
+        neighborhood: &'static str,
+        finished: Arc<AtomicUsize>,
+        recovers_after: Option<usize>,
+        breaks_after: Option<usize>,
+        calls: AtomicUsize,
+    }
+
+    impl Fake {
+        fn ok(name: &'static str) -> Self {
+            Fake {
+                name,
+                delay: Duration::from_millis(0),
+                outcome: Ok(()),
+                neighborhood: "Zona Cívico-Administrativa",
+                finished: Arc::new(AtomicUsize::new(0)),
+                recovers_after: None,
+                breaks_after: None,
+                calls: AtomicUsize::new(0),
+            }
+        }
+
+        /// err returns a fake that fails with the error of the given status code
+        fn err(name: &'static str, code: u16) -> Self {
+            Fake {
+                outcome: Err(match code {
+                    500..=599 => Kind::ServerError { code },
+                    _ => Kind::ClientError { code },
+                }),
+                ..Fake::ok(name)
+            }
+        }
+
+        /// not_found returns a fake that reports every CEP as not found
+        fn not_found(name: &'static str) -> Self {
+            Fake {
+                outcome: Err(Kind::NotFound),
+                ..Fake::ok(name)
+            }
+        }
+
+        fn after(mut self, millis: u64) -> Self {
+            self.delay = Duration::from_millis(millis);
+            self
+        }
+
+        fn neighborhood(mut self, neighborhood: &'static str) -> Self {
+            self.neighborhood = neighborhood;
+            self
+        }
+
+        /// counting increments `finished` whenever a lookup runs to completion
+        fn counting(mut self, finished: &Arc<AtomicUsize>) -> Self {
+            self.finished = finished.clone();
+            self
+        }
+
+        /// recovers_after makes an erroring fake answer with an address after this number of failed lookups
+        fn recovers_after(mut self, failures: usize) -> Self {
+            self.recovers_after = Some(failures);
+            self
+        }
+
+        /// breaks_after makes the fake fail with a 503 status after this number of lookups
+        fn breaks_after(mut self, lookups: usize) -> Self {
+            self.breaks_after = Some(lookups);
+            self
+        }
+    }
+
+    impl CepProvider for Fake {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn lookup<'a>(&'a self, cep: &'a str) -> BoxFuture<'a, Result<Address, Error>> {
+            async move {
+                async_std::task::sleep(self.delay).await;
+                self.finished.fetch_add(1, Ordering::SeqCst);
+                let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+                let outcome = match self.breaks_after {
+                    Some(n) if call > n => Err(Kind::ServerError { code: 503 }),
+                    _ => self.outcome.clone(),
+                };
+                match outcome {
+                    Err(kind) if self.recovers_after.is_none_or(|n| call <= n) => Err(Error {
+                        source: self.source(),
+                        kind,
+                    }),
+                    _ => Ok(Address {
+                        cep: cep.to_owned(),
+                        address: "SPP".to_string(),
+                        details: self.name.to_string(),
+                        neighborhood: self.neighborhood.to_string(),
+                        city: "Brasília".to_string(),
+                        state: "DF".to_string(),
+                        ..Default::default()
+                    }),
+                }
+            }
+            .boxed()
+        }
+    }
+
+    fn empty_builder() -> super::LagoinhaBuilder {
+        Lagoinha::builder()
+            .disable(Service::Viacep)
+            .disable(Service::Correios)
+            .disable(Service::Cepla)
+    }
+
+    #[test]
+    fn builder_selects_providers() {
+        let client = Lagoinha::builder()
+            .disable(Service::Correios)
+            .disable(Service::Cepla)
+            .enable(Service::Viacep)
+            .provider(Fake::ok("custom"))
+            .build();
+        assert_eq!(client.providers(), vec!["viacep", "custom"]);
+    }
+
+    #[test]
+    fn custom_provider_only() {
+        let client = empty_builder().provider(Fake::ok("custom")).build();
+
+        let addr = async_std::task::block_on(client.get_address("70150903")).unwrap();
+        assert_eq!(addr.cep, "70150903");
+        assert_eq!(addr.details, "custom");
+    }
+
+    #[tokio::test]
+    async fn custom_provider_error() {
+        let client = empty_builder().provider(Fake::err("custom", 404)).build();
+
+        let err = client.get_address("70150903").await.unwrap_err();
+        assert_eq!(err.source, Source::LagoinhaLib);
+        assert!(matches!(err.kind, Kind::AllServicesReturnedErrors { .. }));
+    }
+
+    fn delayed_client() -> Lagoinha {
+        empty_builder()
+            .provider(Fake::ok("slow").after(600))
+            .provider(Fake::ok("slower").after(700))
+            .provider(Fake::ok("fast").after(100))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn latency_is_the_fastest_provider_tokio() {
+        let client = delayed_client();
+        let start = Instant::now();
+        let addr = client.get_address("70150903").await.unwrap();
+        assert_eq!(addr.details, "fast");
+        // the sum of the delays is 1400ms
+        assert!(start.elapsed() < Duration::from_millis(400));
+    }
+
+    #[test]
+    fn latency_is_the_fastest_provider_async_std() {
+        let client = delayed_client();
+        let start = Instant::now();
+        let addr = async_std::task::block_on(client.get_address("70150903")).unwrap();
+        assert_eq!(addr.details, "fast");
+        assert!(start.elapsed() < Duration::from_millis(400));
+    }
+
+    #[test]
+    fn early_errors_do_not_end_the_race() {
+        let client = empty_builder()
+            .provider(Fake::err("first", 400))
+            .provider(Fake::ok("last").after(100))
+            .provider(Fake::err("second", 401).after(20))
+            .build();
+
+        let addr = async_std::task::block_on(client.get_address("70150903")).unwrap();
+        assert_eq!(addr.details, "last");
+    }
+
+    #[tokio::test]
+    async fn every_error_is_reported() {
+        let client = empty_builder()
+            .provider(Fake::err("third", 402).after(100))
+            .provider(Fake::err("first", 400))
+            .provider(Fake::err("second", 401).after(50))
+            .build();
+
+        let err = client.get_address("70150903").await.unwrap_err();
+        match err.kind {
+            Kind::AllServicesReturnedErrors { errors } => {
+                let kinds: Vec<Kind> = errors.into_iter().map(|e| e.kind).collect();
+                assert_eq!(
+                    kinds,
+                    vec![
+                        Kind::ClientError { code: 400 },
+                        Kind::ClientError { code: 401 },
+                        Kind::ClientError { code: 402 },
+                    ]
+                );
+            }
+            kind => panic!("unexpected error kind {:?}", kind),
+        }
+    }
+
+    #[tokio::test]
+    async fn losers_are_cancelled() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let client = empty_builder()
+            .provider(Fake::ok("slow").after(200).counting(&finished))
+            .provider(Fake::ok("fast").counting(&finished))
+            .build();
+
+        let addr = client.get_address("70150903").await.unwrap();
+        assert_eq!(addr.details, "fast");
+        async_std::task::sleep(Duration::from_millis(300)).await;
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn slow_providers_time_out() {
+        let client = empty_builder()
+            .timeout(Duration::from_millis(50))
+            .provider(Fake::ok("hung").after(5_000))
+            .build();
+
+        let start = Instant::now();
+        let err = client.get_address("70150903").await.unwrap_err();
+        assert!(start.elapsed() < Duration::from_millis(1_000));
+        match err.kind {
+            Kind::AllServicesReturnedErrors { errors } => {
+                assert_eq!(
+                    errors[0].kind,
+                    Kind::Timeout {
+                        provider: "hung".to_owned(),
+                        after: Duration::from_millis(50),
+                    }
+                );
+                assert_eq!(errors[0].source, Source::Custom("hung".to_owned()));
+                assert!(errors[0].to_string().contains("did not answer"));
+            }
+            kind => panic!("unexpected error kind {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn provider_timeout_overrides_client_timeout() {
+        let client = empty_builder()
+            .timeout(Duration::from_millis(20))
+            .provider_timeout("patient", Duration::from_millis(1_000))
+            .provider(Fake::ok("hasty").after(200))
+            .provider(Fake::ok("patient").after(200))
+            .build();
+
+        assert_eq!(client.timeout("hasty"), Some(Duration::from_millis(20)));
+        assert_eq!(
+            client.timeout("patient"),
+            Some(Duration::from_millis(1_000))
+        );
+        assert_eq!(client.timeout("unknown"), None);
+        let addr = async_std::task::block_on(client.get_address("70150903")).unwrap();
+        assert_eq!(addr.details, "patient");
+    }
+
+    #[tokio::test]
+    async fn deadline_exceeded() {
+        let client = empty_builder()
+            .deadline(Duration::from_millis(50))
+            .provider(Fake::ok("hung").after(5_000))
+            .provider(Fake::err("broken", 500))
+            .build();
+
+        let start = Instant::now();
+        let err = client.get_address("70150903").await.unwrap_err();
+        assert!(start.elapsed() < Duration::from_millis(1_000));
+        assert_eq!(err.source, Source::LagoinhaLib);
+        assert_eq!(
+            err.kind,
+            Kind::DeadlineExceeded {
+                after: Duration::from_millis(50)
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn consensus_waits_for_quorum() {
+        let client = empty_builder()
+            .provider(Fake::ok("a").neighborhood("Asa Norte"))
+            .provider(Fake::err("broken", 500))
+            .provider(Fake::ok("b").after(50))
+            .provider(Fake::ok("c").after(100))
+            .provider(Fake::ok("late").after(5_000))
+            .build();
+
+        let consensus = client
+            .consensus("70150903", Quorum::AtLeast(3))
+            .await
+            .unwrap();
+        assert_eq!(consensus.providers, vec!["a", "b", "c"]);
+        assert_eq!(consensus.address.neighborhood, "Zona Cívico-Administrativa");
+        assert_eq!(consensus.disagreements.len(), 1);
+        assert_eq!(consensus.disagreements[0].field, "neighborhood");
+    }
+
+    #[test]
+    fn consensus_quorum_not_reached() {
+        let client = empty_builder()
+            .provider(Fake::ok("a"))
+            .provider(Fake::err("broken", 500))
+            .build();
+
+        let err = async_std::task::block_on(client.consensus("70150903", Quorum::AtLeast(2)))
+            .unwrap_err();
+        assert_eq!(
+            err.kind,
+            Kind::QuorumNotReached {
+                required: 2,
+                answered: 1
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn consensus_all_within_deadline() {
+        let client = empty_builder()
+            .deadline(Duration::from_millis(100))
+            .provider(Fake::ok("a"))
+            .provider(Fake::ok("b").after(20))
+            .provider(Fake::ok("hung").after(5_000))
+            .build();
+
+        let consensus = client.consensus("70150903", Quorum::All).await.unwrap();
+        assert_eq!(consensus.providers, vec!["a", "b"]);
+        assert!(consensus.unanimous());
+    }
+
+    #[tokio::test]
+    async fn merge_waits_for_every_provider() {
+        let client = empty_builder()
+            .provider(Fake::ok("first").after(50))
+            .provider(Fake::ok("second"))
+            .provider(Fake::err("broken", 500))
+            .build();
+
+        let merged = client.merge("70150903").await.unwrap();
+        assert_eq!(merged.providers, vec!["second", "first"]);
+        // the fakes do not support details, so the first in the pool wins
+        assert_eq!(merged.address.details, "first");
+        assert_eq!(merged.provenance["details"], "first");
+    }
+
+    #[test]
+    fn merge_all_errors() {
+        let client = empty_builder().provider(Fake::err("broken", 500)).build();
+
+        let err = async_std::task::block_on(client.merge("70150903")).unwrap_err();
+        assert!(matches!(err.kind, Kind::AllServicesReturnedErrors { .. }));
+    }
+
+    #[test]
+    fn priority_reorders_the_pool() {
+        let client = Lagoinha::builder()
+            .provider(Fake::ok("custom"))
+            .priority(&["custom", "cepla"])
+            .build();
+        assert_eq!(
+            client.providers(),
+            vec!["custom", "cepla", "viacep", "correios"]
+        );
+    }
+
+    #[tokio::test]
+    async fn fallback_follows_the_pool_order() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let client = empty_builder()
+            .strategy(Strategy::Fallback { hedge: None })
+            .provider(Fake::err("broken", 500).counting(&finished))
+            .provider(Fake::ok("slow").after(100).counting(&finished))
+            .provider(Fake::ok("fast").counting(&finished))
+            .build();
+
+        let addr = client.get_address("70150903").await.unwrap();
+        assert_eq!(addr.details, "slow");
+        // the last provider is never called
+        assert_eq!(finished.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn fallback_with_hedge() {
+        let client = empty_builder()
+            .strategy(Strategy::Fallback {
+                hedge: Some(Duration::from_millis(50)),
+            })
+            .provider(Fake::ok("slow").after(1_000))
+            .provider(Fake::ok("fast").after(10))
+            .build();
+
+        let start = Instant::now();
+        let addr = client.get_address("70150903").await.unwrap();
+        assert_eq!(addr.details, "fast");
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn lookup_reports_every_attempt() {
+        let client = empty_builder()
+            .provider_timeout("hung", Duration::from_millis(20))
+            .provider(Fake::ok("slow").after(1_000))
+            .provider(Fake::err("broken", 500))
+            .provider(Fake::ok("winner").after(100))
+            .provider(Fake::ok("hung").after(5_000))
+            .build();
+
+        let lookup = client.lookup("70150903").await.unwrap();
+        assert_eq!(lookup.address.details, "winner");
+        assert_eq!(lookup.provider, "winner");
+        assert_eq!(lookup.source, Source::Custom("winner".to_owned()));
+        assert!(lookup.latency >= Duration::from_millis(100));
+
+        let outcomes: Vec<(&str, &Outcome)> = lookup
+            .attempts
+            .iter()
+            .map(|a| (a.provider.as_str(), &a.outcome))
+            .collect();
+        assert_eq!(outcomes[0], ("slow", &Outcome::Cancelled));
+        assert_eq!(outcomes[1].0, "broken");
+        assert!(matches!(outcomes[1].1, Outcome::Error(_)));
+        assert_eq!(outcomes[2], ("winner", &Outcome::Success));
+        assert_eq!(outcomes[3], ("hung", &Outcome::TimedOut));
+        assert!(lookup.attempts[0].latency >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn lookup_fallback_only_reports_called_providers() {
+        let client = empty_builder()
+            .strategy(Strategy::Fallback { hedge: None })
+            .provider(Fake::err("broken", 500))
+            .provider(Fake::ok("second"))
+            .provider(Fake::ok("third"))
+            .build();
+
+        let lookup = async_std::task::block_on(client.lookup("70150903")).unwrap();
+        assert_eq!(lookup.provider, "second");
+        assert_eq!(lookup.attempts.len(), 2);
+    }
+
+    #[test]
+    fn batch_deduplicates() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let client = empty_builder()
+            .provider(Fake::ok("custom").counting(&finished))
+            .build();
+
+        let ceps = vec!["70150-903", "70150903", "01001000", "70150903"];
+        let results = async_std::task::block_on(client.batch(ceps));
+        assert_eq!(results.len(), 3);
+        assert_eq!(finished.load(Ordering::SeqCst), 2);
+        assert_eq!(results["01001000"].as_ref().unwrap().cep, "01001000");
+        assert!(results["70150-903"].is_ok());
+        assert!(results["70150903"].is_ok());
+    }
+
+    #[tokio::test]
+    async fn batch_respects_concurrency() {
+        let client = empty_builder()
+            .concurrency(2)
+            .provider(Fake::ok("custom").after(50))
+            .build();
+
+        let ceps: Vec<String> = (0..6).map(|i| format!("7015090{}", i)).collect();
+        let start = Instant::now();
+        let results: Vec<_> = client.batch_stream(ceps).collect().await;
+        // 6 lookups of 50ms, 2 at a time
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert_eq!(results.len(), 6);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn provider_concurrency_is_shared_by_lookups() {
+        let client = empty_builder()
+            .provider_concurrency("custom", 1)
+            .provider(Fake::ok("custom").after(30))
+            .build();
+
+        let start = Instant::now();
+        // distinct CEPs, as lookups of the same one share a single call
+        let ceps = ["70150901", "70150902", "70150903", "70150904"];
+        let lookups = ceps.iter().map(|cep| client.get_address(cep));
+        let results = futures::future::join_all(lookups).await;
+        assert!(start.elapsed() >= Duration::from_millis(120));
+        assert!(results.iter().all(|result| result.is_ok()));
+    }
+
+    fn quick_retries(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy::new(max_attempts).backoff(Duration::from_millis(1), Duration::from_millis(5))
+    }
+
+    #[tokio::test]
+    async fn transient_errors_are_retried() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let client = empty_builder()
+            .retry(quick_retries(3))
+            .provider(
+                Fake::err("flaky", 503)
+                    .recovers_after(2)
+                    .counting(&finished),
+            )
+            .build();
+
+        let lookup = client.lookup("70150903").await.unwrap();
+        assert_eq!(lookup.provider, "flaky");
+        assert_eq!(finished.load(Ordering::SeqCst), 3);
+        // retries are part of a single attempt
+        assert_eq!(lookup.attempts.len(), 1);
+    }
+
+    #[test]
+    fn retries_give_up_after_max_attempts() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let client = empty_builder()
+            .retry(quick_retries(3))
+            .provider(Fake::err("broken", 500).counting(&finished))
+            .build();
+
+        let err = async_std::task::block_on(client.get_address("70150903")).unwrap_err();
+        assert!(matches!(err.kind, Kind::AllServicesReturnedErrors { .. }));
+        assert_eq!(finished.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn permanent_errors_are_not_retried() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let client = empty_builder()
+            .retry(quick_retries(3))
+            .provider(Fake::err("missing", 404).counting(&finished))
+            .build();
+
+        assert!(async_std::task::block_on(client.get_address("70150903")).is_err());
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn provider_retry_overrides_client_retry() {
+        let client = empty_builder()
+            .retry(quick_retries(3))
+            .provider_retry("flaky", RetryPolicy::none())
+            .provider(Fake::err("flaky", 503).recovers_after(1))
+            .build();
+
+        assert!(async_std::task::block_on(client.get_address("70150903")).is_err());
+    }
+
+    #[tokio::test]
+    async fn open_breakers_skip_the_provider() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let client = empty_builder()
+            .strategy(Strategy::Fallback { hedge: None })
+            .circuit_breaker(BreakerPolicy::new(2, Duration::from_millis(100)))
+            .provider(Fake::err("down", 500).recovers_after(2).counting(&finished))
+            .provider(Fake::ok("backup"))
+            .build();
+
+        for _ in 0..3 {
+            assert_eq!(
+                client.get_address("70150903").await.unwrap().details,
+                "backup"
+            );
+        }
+        // the third lookup skipped the provider
+        assert_eq!(finished.load(Ordering::SeqCst), 2);
+        assert!(matches!(
+            client.breaker_state("down"),
+            Some(BreakerState::Open { .. })
+        ));
+        assert_eq!(client.breaker_state("backup"), Some(BreakerState::Closed));
+        assert_eq!(client.breaker_state("unknown"), None);
+
+        let lookup = client.lookup("70150903").await.unwrap();
+        match &lookup.attempts[0].outcome {
+            Outcome::Error(e) => assert!(matches!(e.kind, Kind::CircuitOpen { .. })),
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+
+        // after the cool-down, a successful probe closes the breaker
+        async_std::task::sleep(Duration::from_millis(150)).await;
+        assert_eq!(client.breaker_state("down"), Some(BreakerState::HalfOpen));
+        assert_eq!(
+            client.get_address("70150903").await.unwrap().details,
+            "down"
+        );
+        assert_eq!(client.breaker_state("down"), Some(BreakerState::Closed));
+    }
+
+    #[test]
+    fn provider_circuit_breaker_overrides_client_circuit_breaker() {
+        let client = empty_builder()
+            .circuit_breaker(BreakerPolicy::new(1, Duration::from_secs(60)))
+            .provider_circuit_breaker("tolerated", BreakerPolicy::new(10, Duration::from_secs(60)))
+            .provider(Fake::err("strict", 500))
+            .provider(Fake::err("tolerated", 500))
+            .build();
+
+        assert!(async_std::task::block_on(client.get_address("70150903")).is_err());
+        assert!(matches!(
+            client.breaker_state("strict"),
+            Some(BreakerState::Open { .. })
+        ));
+        assert_eq!(
+            client.breaker_state("tolerated"),
+            Some(BreakerState::Closed)
+        );
+    }
+
+    #[tokio::test]
+    async fn adaptive_ranking_prefers_the_fastest() {
+        let client = empty_builder()
+            .strategy(Strategy::Fallback { hedge: None })
+            .adaptive_ranking(true)
+            .provider(Fake::ok("slow").after(50))
+            .provider(Fake::ok("fast"))
+            .build();
+
+        let mut winners = vec![];
+        for _ in 0..3 {
+            winners.push(client.lookup("70150903").await.unwrap().provider);
+        }
+        // the fast provider is only called once it has no statistics, and then it ranks first
+        assert_eq!(winners, vec!["slow", "fast", "fast"]);
+        assert_eq!(client.ranking(), vec!["fast", "slow"]);
+
+        let stats = client.stats();
+        assert_eq!(stats[0].provider, "slow");
+        assert_eq!(stats[0].calls, 1);
+        assert!(stats[0].p50.unwrap() >= Duration::from_millis(50));
+        assert_eq!(stats[1].calls, 2);
+    }
+
+    #[test]
+    fn ranking_follows_the_pool_without_adaptive_ranking() {
+        let client = empty_builder()
+            .provider(Fake::err("broken", 500))
+            .provider(Fake::ok("working"))
+            .build();
+
+        assert!(async_std::task::block_on(client.get_address("70150903")).is_ok());
+        assert_eq!(client.stats()[0].error_rate, 1.0);
+        assert_eq!(client.ranking(), vec!["broken", "working"]);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_lookups_wait() {
+        let client = empty_builder()
+            .rate_limit(RateLimit::new(1, Duration::from_millis(50)))
+            .provider(Fake::ok("custom"))
+            .build();
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(client.get_address("70150903").await.is_ok());
+        }
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[tokio::test]
+    async fn rate_limited_providers_can_be_skipped() {
+        let client = empty_builder()
+            .provider_rate_limit(
+                "limited",
+                RateLimit::new(1, Duration::from_secs(60)).wait(false),
+            )
+            .provider(Fake::ok("limited"))
+            .provider(Fake::ok("backup").after(20))
+            .build();
+
+        assert_eq!(
+            client.get_address("70150903").await.unwrap().details,
+            "limited"
+        );
+        let lookup = client.lookup("70150903").await.unwrap();
+        assert_eq!(lookup.provider, "backup");
+        match &lookup.attempts[0].outcome {
+            Outcome::Error(e) => assert!(matches!(e.kind, Kind::RateLimited { .. })),
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn daily_quota_is_exhausted() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let client = empty_builder()
+            .retry(quick_retries(3))
+            .rate_limit(RateLimit::unlimited().daily_quota(2))
+            .provider(Fake::ok("custom").counting(&finished))
+            .build();
+
+        for _ in 0..2 {
+            assert!(async_std::task::block_on(client.get_address("70150903")).is_ok());
+        }
+        let lookup = async_std::task::block_on(client.lookup("70150903"));
+        assert!(lookup.is_err());
+        assert_eq!(finished.load(Ordering::SeqCst), 2);
+
+        let err = async_std::task::block_on(client.get_address("70150903")).unwrap_err();
+        match err.kind {
+            Kind::AllServicesReturnedErrors { errors } => assert_eq!(
+                errors[0].kind,
+                Kind::QuotaExhausted {
+                    provider: "custom".to_owned(),
+                    quota: 2,
+                }
+            ),
+            kind => panic!("unexpected error kind {:?}", kind),
+        }
+    }
+
+    #[tokio::test]
+    async fn cache_serves_repeated_lookups() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let client = empty_builder()
+            .cache(10, Duration::from_secs(60))
+            .provider(Fake::ok("custom").counting(&finished))
+            .build();
+
+        let first = client.lookup("70150-903").await.unwrap();
+        assert!(!first.cached);
+        let second = client.lookup("70150903").await.unwrap();
+        assert!(second.cached);
+        assert_eq!(second.provider, "custom");
+        assert_eq!(second.address, first.address);
+        assert!(second.attempts.is_empty());
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+
+        let stats = client.cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+    }
+
+    #[test]
+    fn cache_expires_and_skips_errors() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let client = empty_builder()
+            .cache(10, Duration::from_millis(20))
+            .provider(
+                Fake::err("flaky", 500)
+                    .recovers_after(1)
+                    .counting(&finished),
+            )
+            .build();
+
+        assert!(async_std::task::block_on(client.get_address("70150903")).is_err());
+        assert!(async_std::task::block_on(client.get_address("70150903")).is_ok());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(async_std::task::block_on(client.get_address("70150903")).is_ok());
+        assert_eq!(finished.load(Ordering::SeqCst), 3);
+        assert_eq!(client.cache_stats().unwrap().hits, 0);
+        assert_eq!(empty_builder().build().cache_stats(), None);
+    }
+
+    #[tokio::test]
+    async fn negative_cache_remembers_missing_ceps() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let client = empty_builder()
+            .cache(10, Duration::from_secs(60))
+            .negative_cache(Duration::from_millis(50))
+            .provider(Fake::not_found("missing").counting(&finished))
+            .provider(Fake::err("broken", 500).counting(&finished))
+            .build();
+
+        for _ in 0..2 {
+            let err = client.get_address("99999999").await.unwrap_err();
+            assert_eq!(err.kind, Kind::NotFound);
+        }
+        assert_eq!(finished.load(Ordering::SeqCst), 2);
+
+        // the negative entry expires before the positive TTL
+        async_std::task::sleep(Duration::from_millis(60)).await;
+        assert!(client.get_address("99999999").await.is_err());
+        assert_eq!(finished.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn not_found_when_the_providers_agree() {
+        let client = empty_builder()
+            .provider(Fake::not_found("missing"))
+            .provider(Fake::err("broken", 503))
+            .build();
+        let err = client.get_address("99999999").await.unwrap_err();
+        assert_eq!(
+            err,
+            Error {
+                source: Source::LagoinhaLib,
+                kind: Kind::NotFound,
+            }
+        );
+
+        // an address from any provider wins
+        let client = empty_builder()
+            .provider(Fake::not_found("missing"))
+            .provider(Fake::ok("found").after(20))
+            .build();
+        assert!(client.get_address("70150903").await.is_ok());
+
+        // a provider that rejects the CEP as malformed contradicts the others
+        let client = empty_builder()
+            .provider(Fake::not_found("missing"))
+            .provider(Fake::err("invalid", 400))
+            .build();
+        let err = client.get_address("123").await.unwrap_err();
+        assert!(matches!(err.kind, Kind::AllServicesReturnedErrors { .. }));
+    }
+
+    #[test]
+    fn outages_are_not_cached_as_missing() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let client = empty_builder()
+            .cache(10, Duration::from_secs(60))
+            .negative_cache(Duration::from_secs(60))
+            .provider(Fake::err("broken", 503).counting(&finished))
+            .build();
+
+        for _ in 0..2 {
+            let err = async_std::task::block_on(client.get_address("70150903")).unwrap_err();
+            assert!(matches!(err.kind, Kind::AllServicesReturnedErrors { .. }));
+        }
+        assert_eq!(finished.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn stale_entries_are_revalidated_in_the_background() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let client = empty_builder()
+            .cache(10, Duration::from_millis(100))
+            .stale_while_revalidate(Duration::from_secs(60))
+            .provider(Fake::ok("slow").after(100).counting(&finished))
+            .build();
+
+        assert!(!client.lookup("70150903").await.unwrap().stale);
+        async_std::task::sleep(Duration::from_millis(110)).await;
+
+        let start = Instant::now();
+        let stale = client.lookup("70150903").await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert!(stale.cached && stale.stale);
+        // a refresh is already running
+        assert!(client.lookup("70150903").await.unwrap().stale);
+
+        // the refresh takes 100ms, and leaves a fresh entry
+        async_std::task::sleep(Duration::from_millis(130)).await;
+        assert_eq!(finished.load(Ordering::SeqCst), 2);
+        let fresh = client.lookup("70150903").await.unwrap();
+        assert!(fresh.cached && !fresh.stale);
+    }
+
+    #[tokio::test]
+    async fn stale_entries_are_served_when_every_provider_fails() {
+        let client = empty_builder()
+            .cache(10, Duration::from_millis(20))
+            .serve_stale_on_error(Duration::from_secs(60))
+            .provider(Fake::ok("custom").breaks_after(1))
+            .build();
+
+        assert!(client.lookup("70150903").await.is_ok());
+        async_std::task::sleep(Duration::from_millis(30)).await;
+
+        let lookup = client.lookup("70150903").await.unwrap();
+        assert!(lookup.stale);
+        assert_eq!(lookup.provider, "custom");
+        assert_eq!(client.cache_stats().unwrap().misses, 2);
+
+        // without the policy, the error is returned
+        let client = empty_builder()
+            .cache(10, Duration::from_millis(20))
+            .provider(Fake::ok("custom").breaks_after(1))
+            .build();
+        assert!(client.lookup("70150903").await.is_ok());
+        async_std::task::sleep(Duration::from_millis(30)).await;
+        assert!(client.lookup("70150903").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn concurrent_lookups_share_one_race() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let client = empty_builder()
+            .provider(Fake::ok("custom").after(50).counting(&finished))
+            .build();
+
+        let ceps = ["70150-903", "70150903", "70150903", " 70150903"];
+        let lookups = ceps.iter().map(|cep| client.lookup(cep));
+        let results = futures::future::join_all(lookups).await;
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+        let first = results[0].as_ref().unwrap();
+        assert!(results.iter().all(|result| result.as_ref() == Ok(first)));
+        assert!(client.inner.in_flight.lock().unwrap().is_empty());
+
+        // once finished, the next lookup starts a new race
+        assert!(client.lookup("70150903").await.is_ok());
+        assert_eq!(finished.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn dropped_lookups_cancel_the_shared_race() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let client = empty_builder()
+            .provider(Fake::ok("custom").after(50).counting(&finished))
+            .build();
+
+        let abandoned = async_std::future::timeout(
+            Duration::from_millis(10),
+            futures::future::join(client.lookup("70150903"), client.lookup("70150903")),
+        );
+        assert!(abandoned.await.is_err());
+        assert!(client.lookup("70150-903").await.is_ok());
+        assert!(client.inner.in_flight.lock().unwrap().is_empty());
+        async_std::task::sleep(Duration::from_millis(100)).await;
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(feature = "json-cache")]
+    #[test]
+    fn persistent_cache_outlives_the_client() {
+        use crate::cache::file::JsonFileCache;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        let client = |provider: Fake| {
+            empty_builder()
+                .cache_backend(JsonFileCache::open(&path).unwrap(), Duration::from_secs(60))
+                .provider(provider)
+                .build()
+        };
+
+        let first = client(Fake::ok("custom"));
+        assert!(async_std::task::block_on(first.get_address("70150903")).is_ok());
+        drop(first);
+
+        // the provider of the new client is down, but the lookup is in the file
+        let second = client(Fake::err("custom", 503));
+        let lookup = async_std::task::block_on(second.lookup("70150-903")).unwrap();
+        assert!(lookup.cached);
+        assert_eq!(lookup.provider, "custom");
+        assert_eq!(lookup.address.cep, "70150903");
+    }
+
+    fn assert_send<T: Send>(_: T) {}
+
+    #[test]
+    fn futures_are_send() {
+        let client = Lagoinha::default();
+        assert_send(client.lookup("70150903"));
+        assert_send(client.consensus("70150903", Quorum::All));
+        assert_send(client.merge("70150903"));
+        assert_send(client.batch(vec!["70150903"]));
+    }
+
+    #[test]
+    fn empty_pool() {
+        let client = empty_builder().build();
+
+        let err = async_std::task::block_on(client.get_address("70150903")).unwrap_err();
+        assert!(matches!(err.kind, Kind::AllServicesReturnedErrors { .. }));
+    }
+}
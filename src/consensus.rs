@@ -0,0 +1,253 @@
+//! Consensus compares the addresses returned by many providers, and picks the majority answer for each field.
+//!
+//! # Example
+//! ```
+//!extern crate lagoinha;
+//!extern crate tokio;
+//!
+//!use lagoinha::consensus::Quorum;
+//!
+//!#[tokio::main]
+//!async fn main() {
+//!    let client = lagoinha::Lagoinha::default();
+//!    let consensus = client.consensus("70150903", Quorum::AtLeast(2)).await;
+//!    println!("{:#?}", consensus);
+//!}
+//!```
+
+use crate::client::Response;
+use crate::services::{normalize_cep, Address};
+
+/// Quorum sets how many providers must answer before the answers are compared
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quorum {
+    /// AtLeast waits for this number of successful answers, and cancels the remaining requests
+    AtLeast(usize),
+    /// All waits for every provider in the pool, or until the client deadline passes
+    All,
+}
+
+/// Consensus is the majority answer, with a report of where the providers disagreed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Consensus {
+    /// address holds the majority value of each field
+    pub address: Address,
+    /// providers lists the providers that answered, in the order they answered
+    pub providers: Vec<String>,
+    /// disagreements lists the fields where the normalized answers differed
+    pub disagreements: Vec<Disagreement>,
+}
+
+impl Consensus {
+    /// unanimous indicates that every provider that answered agreed on every field
+    pub fn unanimous(&self) -> bool {
+        self.disagreements.is_empty()
+    }
+}
+
+/// Disagreement reports what each provider said about a field
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disagreement {
+    /// field is the name of the services::Address field
+    pub field: &'static str,
+    /// answers holds the value returned by each provider that took part in the vote
+    pub answers: Vec<Answer>,
+}
+
+/// Answer is the value a provider returned for a field
+#[derive(Debug, Clone, PartialEq)]
+pub struct Answer {
+    pub provider: String,
+    pub value: String,
+}
+
+fn set_field(address: &mut Address, name: &str, value: &str) {
+    if let Some(field) = address.field_mut(name) {
+        *field = value.to_owned();
+    }
+}
+
+/// normalize folds a value so that formatting differences are not taken as disagreements:
+/// case, accents, punctuation and repeated spaces are ignored. CEPs only keep their digits.
+pub(crate) fn normalize(name: &str, value: &str) -> String {
+    if name == "cep" {
+        return normalize_cep(value);
+    }
+    value
+        .chars()
+        .filter_map(|c| match c {
+            'á' | 'à' | 'â' | 'ã' | 'ä' | 'Á' | 'À' | 'Â' | 'Ã' | 'Ä' => Some('a'),
+            'é' | 'è' | 'ê' | 'ë' | 'É' | 'È' | 'Ê' | 'Ë' => Some('e'),
+            'í' | 'ì' | 'î' | 'ï' | 'Í' | 'Ì' | 'Î' | 'Ï' => Some('i'),
+            'ó' | 'ò' | 'ô' | 'õ' | 'ö' | 'Ó' | 'Ò' | 'Ô' | 'Õ' | 'Ö' => Some('o'),
+            'ú' | 'ù' | 'û' | 'ü' | 'Ú' | 'Ù' | 'Û' | 'Ü' => Some('u'),
+            'ç' | 'Ç' => Some('c'),
+            c if c.is_alphanumeric() => Some(c.to_ascii_lowercase()),
+            '.' | '\'' => None,
+            _ => Some(' '),
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// vote picks the majority normalized value of each field. Ties go to the provider that answered first.
+/// Providers that do not support a field are left out of its vote.
+/// If no provider supports it, the first non empty value is used without comparing.
+pub(crate) fn vote(responses: &[Response]) -> Option<Consensus> {
+    let first = responses.first()?;
+    let mut address = first.address.clone();
+    let mut disagreements = Vec::new();
+
+    for name in Address::FIELDS.iter() {
+        let voters: Vec<&Response> = responses
+            .iter()
+            .filter(|r| r.capabilities.supports(name))
+            .collect();
+        if voters.is_empty() {
+            if let Some(value) = responses
+                .iter()
+                .filter_map(|r| r.address.field(name))
+                .find(|value| !value.is_empty())
+            {
+                set_field(&mut address, name, value);
+            }
+            continue;
+        }
+
+        // (normalized value, votes, original value of the first voter)
+        let mut tally: Vec<(String, usize, &str)> = Vec::new();
+        for voter in &voters {
+            let value = voter.address.field(name).unwrap_or_default();
+            let normalized = normalize(name, value);
+            match tally.iter_mut().find(|(n, _, _)| *n == normalized) {
+                Some(entry) => entry.1 += 1,
+                None => tally.push((normalized, 1, value)),
+            }
+        }
+
+        let mut winner = &tally[0];
+        for entry in &tally[1..] {
+            if entry.1 > winner.1 {
+                winner = entry;
+            }
+        }
+        set_field(&mut address, name, winner.2);
+
+        if tally.len() > 1 {
+            disagreements.push(Disagreement {
+                field: name,
+                answers: voters
+                    .iter()
+                    .map(|voter| Answer {
+                        provider: voter.provider.clone(),
+                        value: voter.address.field(name).unwrap_or_default().to_owned(),
+                    })
+                    .collect(),
+            });
+        }
+    }
+
+    Some(Consensus {
+        address,
+        providers: responses.iter().map(|r| r.provider.clone()).collect(),
+        disagreements,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, vote, Response};
+    use crate::services::{Address, Capabilities};
+
+    fn response(provider: &str, neighborhood: &str, details: &str, with_details: bool) -> Response {
+        Response {
+            index: 0,
+            provider: provider.to_owned(),
+            capabilities: Capabilities {
+                details: with_details,
+                ..Default::default()
+            },
+            address: Address {
+                cep: "70150903".to_string(),
+                address: "SPP".to_string(),
+                details: details.to_string(),
+                neighborhood: neighborhood.to_string(),
+                city: "Brasília".to_string(),
+                state: "DF".to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn normalize_ignores_formatting() {
+        assert_eq!(
+            normalize("neighborhood", "Zona  Cívico-Administrativa"),
+            normalize("neighborhood", "zona civico administrativa ")
+        );
+        assert_eq!(normalize("cep", "70150-903"), normalize("cep", "70150903"));
+        assert_ne!(
+            normalize("neighborhood", "Asa Sul"),
+            normalize("neighborhood", "Asa Norte")
+        );
+    }
+
+    #[test]
+    fn vote_unanimous() {
+        let responses = vec![
+            response("a", "Zona Cívico-Administrativa", "", true),
+            response("b", "ZONA CIVICO-ADMINISTRATIVA", "", true),
+        ];
+        let consensus = vote(&responses).unwrap();
+        assert!(consensus.unanimous());
+        assert_eq!(consensus.address.neighborhood, "Zona Cívico-Administrativa");
+        assert_eq!(consensus.providers, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn vote_majority_with_report() {
+        let responses = vec![
+            response("a", "Asa Norte", "", true),
+            response("b", "Zona Cívico-Administrativa", "", true),
+            response("c", "Zona Civico Administrativa", "", true),
+        ];
+        let consensus = vote(&responses).unwrap();
+        assert_eq!(consensus.address.neighborhood, "Zona Cívico-Administrativa");
+        assert_eq!(consensus.disagreements.len(), 1);
+
+        let disagreement = &consensus.disagreements[0];
+        assert_eq!(disagreement.field, "neighborhood");
+        assert_eq!(disagreement.answers.len(), 3);
+        assert_eq!(disagreement.answers[0].provider, "a");
+        assert_eq!(disagreement.answers[0].value, "Asa Norte");
+    }
+
+    #[test]
+    fn vote_tie_goes_to_first() {
+        let responses = vec![
+            response("a", "Asa Norte", "", true),
+            response("b", "Asa Sul", "", true),
+        ];
+        let consensus = vote(&responses).unwrap();
+        assert_eq!(consensus.address.neighborhood, "Asa Norte");
+        assert!(!consensus.unanimous());
+    }
+
+    #[test]
+    fn vote_skips_unsupported_fields() {
+        let responses = vec![
+            response("correios", "Asa Sul", "", false),
+            response("viacep", "Asa Sul", "Palácio", true),
+        ];
+        let consensus = vote(&responses).unwrap();
+        assert!(consensus.unanimous());
+        assert_eq!(consensus.address.details, "Palácio");
+    }
+
+    #[test]
+    fn vote_empty() {
+        assert!(vote(&[]).is_none());
+    }
+}
@@ -1,12 +1,18 @@
 use std::error::Error as StdError;
 use std::fmt;
-#[derive(PartialEq, Debug)]
-/// Source represents from what component the error came (core lib, or the respective services)
+use std::sync::Arc;
+use std::time::Duration;
+#[derive(PartialEq, Debug, Clone)]
+/// Source represents from what component the error came (core lib, or the respective services).
+/// New built-in services may be added, so matches on it need a wildcard arm.
+#[non_exhaustive]
 pub enum Source {
     Viacep,
     Correios,
     Cepla,
     LagoinhaLib,
+    /// Custom is a provider added to the client, identified by its name
+    Custom(String),
 }
 
 impl fmt::Display for Source {
@@ -16,11 +22,12 @@ impl fmt::Display for Source {
             Source::Correios => write!(f, "Correios"),
             Source::Cepla => write!(f, "Cepla"),
             Source::LagoinhaLib => write!(f, "Lagoinha"),
+            Source::Custom(name) => write!(f, "{}", name),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Error {
     /// Source represents from what component the error came (core lib, or the respective services)
     pub source: Source,
@@ -28,7 +35,117 @@ pub struct Error {
     pub kind: Kind,
 }
 
-#[derive(Debug, PartialEq)]
+impl Error {
+    /// is_retryable indicates whether the same request is likely to succeed if it is retried soon:
+    /// server errors, throttling, timeouts, network failures and responses that could not be read.
+    /// A failed lookup is retryable if any of its services failed this way.
+    pub fn is_retryable(&self) -> bool {
+        match &self.kind {
+            Kind::ServerError { .. }
+            | Kind::Throttled { .. }
+            | Kind::Timeout { .. }
+            | Kind::DeadlineExceeded { .. }
+            | Kind::QuorumNotReached { .. }
+            | Kind::Network { .. }
+            | Kind::NetworkTimeout { .. }
+            | Kind::MissingBodyError => true,
+            Kind::ClientError { code } => *code == 408 || *code == 429,
+            Kind::SoapFault { code, .. } => *code >= 500,
+            Kind::AllServicesReturnedErrors { errors } => errors.iter().any(Error::is_retryable),
+            _ => false,
+        }
+    }
+
+    /// is_permanent indicates whether the same request will keep failing, like a CEP that does not exist,
+    /// or an answer the library cannot parse. A failed lookup is permanent if all of its services failed this way.
+    /// Errors that depend on the state of the client, like a skipped provider, are neither permanent nor retryable.
+    pub fn is_permanent(&self) -> bool {
+        match &self.kind {
+            Kind::NotFound
+            | Kind::InputError
+            | Kind::BodyParsingError { .. }
+            | Kind::UnknownServerError { .. }
+            | Kind::UnexpectedLibraryError
+            | Kind::Tls { .. } => true,
+            Kind::ClientError { code } => *code != 408 && *code != 429,
+            Kind::SoapFault { code, .. } => *code < 500,
+            Kind::AllServicesReturnedErrors { errors } => {
+                !errors.is_empty() && errors.iter().all(Error::is_permanent)
+            }
+            _ => false,
+        }
+    }
+
+    /// is_not_found indicates that the CEP does not exist
+    pub fn is_not_found(&self) -> bool {
+        self.kind == Kind::NotFound
+    }
+
+    /// is_input_error indicates that the CEP was rejected as malformed, by the library or by any of the services
+    pub fn is_input_error(&self) -> bool {
+        match &self.kind {
+            Kind::InputError | Kind::ClientError { code: 400 } => true,
+            Kind::AllServicesReturnedErrors { errors } => errors.iter().any(Error::is_input_error),
+            _ => false,
+        }
+    }
+
+    /// is_upstream_outage indicates that the service is in trouble: it failed, answered something unexpected,
+    /// could not be reached, or is skipped because its circuit breaker is open.
+    /// A failed lookup is an outage if all of its services failed this way, or if it ran out of time.
+    pub fn is_upstream_outage(&self) -> bool {
+        match &self.kind {
+            Kind::ServerError { .. }
+            | Kind::UnknownServerError { .. }
+            | Kind::Throttled { .. }
+            | Kind::Timeout { .. }
+            | Kind::DeadlineExceeded { .. }
+            | Kind::QuorumNotReached { .. }
+            | Kind::CircuitOpen { .. }
+            | Kind::Network { .. }
+            | Kind::Tls { .. }
+            | Kind::NetworkTimeout { .. }
+            | Kind::MissingBodyError
+            | Kind::BodyParsingError { .. } => true,
+            Kind::SoapFault { code, .. } => *code >= 500,
+            Kind::AllServicesReturnedErrors { errors } => {
+                !errors.is_empty() && errors.iter().all(Error::is_upstream_outage)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Cause holds the underlying error of a failed request, like the isahc::Error of a refused connection.
+/// It is shared, so that errors stay cheap to clone, and two causes are equal when they describe the same failure.
+#[derive(Debug, Clone)]
+pub struct Cause(Arc<dyn StdError + Send + Sync>);
+
+impl Cause {
+    /// new wraps an error as a Cause
+    pub fn new<E: StdError + Send + Sync + 'static>(error: E) -> Self {
+        Cause(Arc::new(error))
+    }
+
+    /// get_ref returns the underlying error, which can be downcast to its original type
+    pub fn get_ref(&self) -> &(dyn StdError + Send + Sync + 'static) {
+        &*self.0
+    }
+}
+
+impl PartialEq for Cause {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_string() == other.0.to_string()
+    }
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Kind {
     /// UnknownServerError represents unmapped server errors, with the received code
     UnknownServerError { code: u16 },
@@ -38,14 +155,40 @@ pub enum Kind {
     ClientError { code: u16 },
     /// BodyParsingError represents an error where the received body does not match with the expected schema
     BodyParsingError { error: String, body: String },
-    /// AllServicesReturnedErrors indicates that each one of the called services returned an error
-    AllServicesReturnedErrors { e1: String, e2: String, e3: String },
+    /// AllServicesReturnedErrors indicates that each one of the called services returned an error, holding the errors in the order they arrived
+    AllServicesReturnedErrors { errors: Vec<Error> },
     /// MissingBodyError indicates that the respose had a missing body
     MissingBodyError,
-    /// InputError is unused at the momment, but is intended to represent an error with the input
+    /// InputError represents an error with the input, like a malformed CEP
     InputError,
     /// UnexpectedLibraryError represents an unkown error in the library code
     UnexpectedLibraryError,
+    /// Timeout indicates that the named provider did not answer within its request timeout
+    Timeout { provider: String, after: Duration },
+    /// DeadlineExceeded indicates that the whole lookup did not finish within the client deadline
+    DeadlineExceeded { after: Duration },
+    /// Throttled represents a 429 or 503 status that came with a Retry-After header, holding the requested wait
+    Throttled { code: u16, retry_after: Duration },
+    /// CircuitOpen indicates that the named provider was skipped, because its circuit breaker is open
+    CircuitOpen { provider: String },
+    /// RateLimited indicates that the named provider was skipped, because it is over its rate limit
+    RateLimited { provider: String },
+    /// QuotaExhausted indicates that the named provider was skipped, because its daily quota of requests was used up
+    QuotaExhausted { provider: String, quota: u64 },
+    /// NotFound indicates that the CEP does not exist, as confirmed by the services
+    NotFound,
+    /// CacheError represents a failure of a cache backend, with its description
+    CacheError { error: String },
+    /// SoapFault represents a fault answered by a SOAP service that is not otherwise mapped, with the status code and the fault text
+    SoapFault { code: u16, fault: String },
+    /// Network indicates that the request failed before an answer was received, like a failed name resolution or a refused connection
+    Network { cause: Cause },
+    /// Tls indicates that the secure connection to the service failed, like when its certificate is invalid
+    Tls { cause: Cause },
+    /// NetworkTimeout indicates that the HTTP client gave up waiting for the service
+    NetworkTimeout { cause: Cause },
+    /// QuorumNotReached indicates that fewer providers than required answered a consensus lookup
+    QuorumNotReached { required: usize, answered: usize },
 }
 
 impl fmt::Display for Error {
@@ -88,15 +231,318 @@ impl fmt::Display for Error {
             Kind::UnexpectedLibraryError => {
                 write!(f,"Received an unexpected error from the library from service {}. Please send an issue in GitHub.", self.source)
             }
-            Kind::AllServicesReturnedErrors { e1, e2, e3 } => {
+            Kind::Timeout { provider, after } => {
                 write!(
                     f,
-                    "All services returned an error. \n: {}, \n: {}, \n: {}",
-                    e1, e2, e3
+                    "Service {} ({}) did not answer within {:?}.",
+                    provider, self.source, after
                 )
             }
+            Kind::DeadlineExceeded { after } => {
+                write!(f, "The lookup did not finish within {:?}.", after)
+            }
+            Kind::Throttled { code, retry_after } => {
+                write!(
+                    f,
+                    "Received status {} from service {}, asking to retry after {:?}.",
+                    code, self.source, retry_after
+                )
+            }
+            Kind::CircuitOpen { provider } => {
+                write!(
+                    f,
+                    "Service {} ({}) was skipped, because its circuit breaker is open.",
+                    provider, self.source
+                )
+            }
+            Kind::RateLimited { provider } => {
+                write!(
+                    f,
+                    "Service {} ({}) was skipped, because it is over its rate limit.",
+                    provider, self.source
+                )
+            }
+            Kind::QuotaExhausted { provider, quota } => {
+                write!(
+                    f,
+                    "Service {} ({}) was skipped, because its daily quota of {} requests is exhausted.",
+                    provider, self.source, quota
+                )
+            }
+            Kind::NotFound => {
+                write!(f, "The CEP was not found.")
+            }
+            Kind::CacheError { error } => {
+                write!(f, "The cache failed with error {}.", error)
+            }
+            Kind::SoapFault { code, fault } => {
+                write!(
+                    f,
+                    "Received an error {} from service {}: {}.",
+                    code, self.source, fault
+                )
+            }
+            Kind::Network { cause } => {
+                write!(f, "Could not reach service {}: {}.", self.source, cause)
+            }
+            Kind::Tls { cause } => {
+                write!(
+                    f,
+                    "The secure connection to service {} failed: {}.",
+                    self.source, cause
+                )
+            }
+            Kind::NetworkTimeout { cause } => {
+                write!(
+                    f,
+                    "The request to service {} timed out: {}.",
+                    self.source, cause
+                )
+            }
+            Kind::QuorumNotReached { required, answered } => {
+                write!(
+                    f,
+                    "Only {} of the {} services required for a consensus answered.",
+                    answered, required
+                )
+            }
+            Kind::AllServicesReturnedErrors { errors } => {
+                write!(f, "All services returned an error.")?;
+                for error in errors {
+                    write!(f, " \n: {}", error)?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
-impl StdError for Error {}
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match &self.kind {
+            Kind::Network { cause } | Kind::Tls { cause } | Kind::NetworkTimeout { cause } => {
+                Some(cause.get_ref())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cause, Error, Kind, Source};
+    use std::time::Duration;
+
+    fn sources() -> Vec<Source> {
+        vec![
+            Source::Viacep,
+            Source::Correios,
+            Source::Cepla,
+            Source::LagoinhaLib,
+            Source::Custom("custom".to_owned()),
+        ]
+    }
+
+    // variant numbers every Kind, so that a new one cannot be left out of the table below
+    fn variant(kind: &Kind) -> usize {
+        match kind {
+            Kind::UnknownServerError { .. } => 0,
+            Kind::ServerError { .. } => 1,
+            Kind::ClientError { .. } => 2,
+            Kind::BodyParsingError { .. } => 3,
+            Kind::AllServicesReturnedErrors { .. } => 4,
+            Kind::MissingBodyError => 5,
+            Kind::InputError => 6,
+            Kind::UnexpectedLibraryError => 7,
+            Kind::Timeout { .. } => 8,
+            Kind::DeadlineExceeded { .. } => 9,
+            Kind::Throttled { .. } => 10,
+            Kind::CircuitOpen { .. } => 11,
+            Kind::RateLimited { .. } => 12,
+            Kind::QuotaExhausted { .. } => 13,
+            Kind::NotFound => 14,
+            Kind::CacheError { .. } => 15,
+            Kind::SoapFault { .. } => 16,
+            Kind::Network { .. } => 17,
+            Kind::Tls { .. } => 18,
+            Kind::NetworkTimeout { .. } => 19,
+            Kind::QuorumNotReached { .. } => 20,
+        }
+    }
+    const VARIANTS: usize = 21;
+
+    fn error(source: Source, kind: Kind) -> Error {
+        Error { source, kind }
+    }
+
+    fn cause() -> Cause {
+        Cause::new(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+    }
+
+    /// Class is the expected classification: retryable, permanent, not found, input error and upstream outage
+    type Class = (bool, bool, bool, bool, bool);
+
+    const RETRYABLE_OUTAGE: Class = (true, false, false, false, true);
+    const PERMANENT: Class = (false, true, false, false, false);
+    const PERMANENT_OUTAGE: Class = (false, true, false, false, true);
+    const NEITHER: Class = (false, false, false, false, false);
+
+    fn classify(error: &Error) -> Class {
+        (
+            error.is_retryable(),
+            error.is_permanent(),
+            error.is_not_found(),
+            error.is_input_error(),
+            error.is_upstream_outage(),
+        )
+    }
+
+    fn table(source: &Source) -> Vec<(Kind, Class)> {
+        let provider = || "viacep".to_owned();
+        let after = Duration::from_secs(1);
+        let nested = |kind: Kind| error(source.clone(), kind);
+        vec![
+            (Kind::UnknownServerError { code: 302 }, PERMANENT_OUTAGE),
+            (Kind::ServerError { code: 500 }, RETRYABLE_OUTAGE),
+            (Kind::ServerError { code: 503 }, RETRYABLE_OUTAGE),
+            (
+                Kind::ClientError { code: 400 },
+                (false, true, false, true, false),
+            ),
+            (Kind::ClientError { code: 404 }, PERMANENT),
+            (
+                Kind::ClientError { code: 408 },
+                (true, false, false, false, false),
+            ),
+            (
+                Kind::ClientError { code: 429 },
+                (true, false, false, false, false),
+            ),
+            (
+                Kind::BodyParsingError {
+                    error: String::new(),
+                    body: String::new(),
+                },
+                PERMANENT_OUTAGE,
+            ),
+            (Kind::AllServicesReturnedErrors { errors: vec![] }, NEITHER),
+            (
+                Kind::AllServicesReturnedErrors {
+                    errors: vec![
+                        nested(Kind::ServerError { code: 500 }),
+                        nested(Kind::Network { cause: cause() }),
+                    ],
+                },
+                RETRYABLE_OUTAGE,
+            ),
+            (
+                Kind::AllServicesReturnedErrors {
+                    errors: vec![
+                        nested(Kind::ServerError { code: 500 }),
+                        nested(Kind::ClientError { code: 404 }),
+                    ],
+                },
+                (true, false, false, false, false),
+            ),
+            (
+                Kind::AllServicesReturnedErrors {
+                    errors: vec![nested(Kind::InputError), nested(Kind::NotFound)],
+                },
+                (false, true, false, true, false),
+            ),
+            (Kind::MissingBodyError, RETRYABLE_OUTAGE),
+            (Kind::InputError, (false, true, false, true, false)),
+            (Kind::UnexpectedLibraryError, PERMANENT),
+            (
+                Kind::Timeout {
+                    provider: provider(),
+                    after,
+                },
+                RETRYABLE_OUTAGE,
+            ),
+            (Kind::DeadlineExceeded { after }, RETRYABLE_OUTAGE),
+            (
+                Kind::Throttled {
+                    code: 429,
+                    retry_after: after,
+                },
+                RETRYABLE_OUTAGE,
+            ),
+            (
+                Kind::CircuitOpen {
+                    provider: provider(),
+                },
+                (false, false, false, false, true),
+            ),
+            (
+                Kind::RateLimited {
+                    provider: provider(),
+                },
+                NEITHER,
+            ),
+            (
+                Kind::QuotaExhausted {
+                    provider: provider(),
+                    quota: 10,
+                },
+                NEITHER,
+            ),
+            (Kind::NotFound, (false, true, true, false, false)),
+            (
+                Kind::CacheError {
+                    error: String::new(),
+                },
+                NEITHER,
+            ),
+            (
+                Kind::SoapFault {
+                    code: 500,
+                    fault: String::new(),
+                },
+                RETRYABLE_OUTAGE,
+            ),
+            (
+                Kind::SoapFault {
+                    code: 400,
+                    fault: String::new(),
+                },
+                PERMANENT,
+            ),
+            (Kind::Network { cause: cause() }, RETRYABLE_OUTAGE),
+            (Kind::Tls { cause: cause() }, PERMANENT_OUTAGE),
+            (Kind::NetworkTimeout { cause: cause() }, RETRYABLE_OUTAGE),
+            (
+                Kind::QuorumNotReached {
+                    required: 2,
+                    answered: 1,
+                },
+                RETRYABLE_OUTAGE,
+            ),
+        ]
+    }
+
+    #[test]
+    fn classification_of_every_kind_and_source() {
+        for source in sources() {
+            let table = table(&source);
+            let mut covered = [false; VARIANTS];
+            for (kind, class) in table {
+                covered[variant(&kind)] = true;
+                let err = error(source.clone(), kind);
+                assert_eq!(classify(&err), class, "{:?}", err);
+                // an error is never both retryable and permanent
+                assert!(!(err.is_retryable() && err.is_permanent()), "{:?}", err);
+            }
+            assert!(covered.iter().all(|&c| c), "every Kind must be classified");
+        }
+    }
+
+    #[test]
+    fn source_of_network_errors() {
+        use std::error::Error as _;
+        let err = error(Source::Viacep, Kind::Network { cause: cause() });
+        let source = err.source().unwrap();
+        assert!(source.downcast_ref::<std::io::Error>().is_some());
+        assert!(error(Source::Viacep, Kind::NotFound).source().is_none());
+    }
+}
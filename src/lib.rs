@@ -5,7 +5,7 @@
 //! # Services
 //!
 //! Currently the services used are : correios, viacep and cepla
-//! It is expected to support adding a custom service to the pool in the future, and the ability to disable the default ones.
+//! The [Lagoinha](client::Lagoinha) client can disable the default ones, and add custom services to the pool.
 //!
 //! While the default http library is Hyper, the CepLá service has an issue with its header implementation, and so the curl library was used. More information in the docs for this service.
 //!
@@ -22,105 +22,39 @@
 //!```
 //!
 
+pub mod breaker;
+pub mod cache;
+pub mod client;
+pub mod consensus;
 pub mod error;
+pub mod merge;
+mod race;
+pub mod ratelimit;
+pub mod report;
+pub mod retry;
 pub mod services;
+pub mod stats;
+pub use client::{Lagoinha, LagoinhaBuilder};
 use error::Error;
-use error::Source::LagoinhaLib;
 use services::Address;
-use services::Addressable;
-
-use async_std::task;
-use futures::channel::mpsc;
-use futures::{future::FutureExt, select, sink::SinkExt, Future};
 use std::time::Duration;
 
-const SEND_ERROR: &str =
-    "Failed awaiting channel send. This should not happen. Please contact the developer";
-
-async fn service_channel_request<Fut, Addr>(
-    f: Fut,
-    error_timeout: u64,
-    mut tx: mpsc::Sender<Result<services::Address, Error>>,
-) where
-    Fut: Future<Output = Result<Addr, Error>>,
-    Addr: Addressable,
-{
-    let addr = f.await;
-    match addr {
-        Ok(addr) => {
-            tx.send(Ok(addr.to_address()))
-                .await
-                .map_err(|e| println!("{} with error: {}", SEND_ERROR, e.to_string()))
-                .ok();
-        }
-        Err(err) => {
-            tx.send(Err(err))
-                .await
-                .map_err(|e| println!("{} with error: {}", SEND_ERROR, e.to_string()))
-                .ok();
-            task::sleep(Duration::from_secs(error_timeout)).await;
-        }
-    }
-}
-
 /// get_address runs concurrent calls to available services requesting the address related to the provided `cep`,
-/// and with a error_timeout in seconds in case some services fail.
+/// and returns the first successful result.
+/// It is a shortcut to a Lagoinha client with the default services. Use the client directly to configure the services pool.
 ///
 /// # Arguments
 ///
 /// * `cep` - A str pointer slice that holds the Brazilian postal code.
-/// * `error_timeout` - Option<u64> timeout in seconds in case some services come to fail. It defaults to 2 if None is provided, and has a minimum value of 1.
-///    This prevents early failures from cancelling possible success results from other services.
+/// * `deadline` - Option<Duration> maximum duration of the whole lookup. There is no deadline if None is provided,
+///   but each service still has to answer within client::DEFAULT_TIMEOUT.
 ///
-pub async fn get_address(cep: &str, error_timeout: Option<u64>) -> Result<Address, Error> {
-    let error_timeout = match error_timeout {
-        Some(cd) => match cd {
-            cd if cd < 2 => 1,
-            cd => cd,
-        },
-        None => 2 as u64,
-    };
-
-    let (tx, mut rx) = mpsc::channel::<Result<services::Address, Error>>(3);
-
-    select! {
-        () = service_channel_request(services::viacep::request(cep), error_timeout, tx.clone()).fuse() => "viacep",
-        () = service_channel_request(services::correios::request(cep), error_timeout, tx.clone()).fuse() => "correios",
-        () = service_channel_request(services::cepla::request(cep), error_timeout, tx.clone()).fuse() => "cepla",
-    };
-
-    let mut error_list: Vec<Error> = Vec::new();
-
-    for _ in 0..3 {
-        let read = rx.try_next();
-        match read {
-            Ok(read_address) => match read_address {
-                Some(read_address) => match read_address {
-                    Ok(addr) => return Ok(addr),
-                    Err(e) => error_list.push(e),
-                },
-                None => error_list.push(Error {
-                    kind: error::Kind::UnexpectedLibraryError,
-                    source: LagoinhaLib,
-                }),
-            },
-            Err(_) => {
-                return Err(Error {
-                    kind: error::Kind::UnexpectedLibraryError,
-                    source: LagoinhaLib,
-                })
-            }
-        };
+pub async fn get_address(cep: &str, deadline: Option<Duration>) -> Result<Address, Error> {
+    let mut builder = Lagoinha::builder();
+    if let Some(deadline) = deadline {
+        builder = builder.deadline(deadline);
     }
-
-    Err(Error {
-        source: error::Source::LagoinhaLib,
-        kind: error::Kind::AllServicesReturnedErrors {
-            e1: format!("{}", error_list[0]),
-            e2: format!("{}", error_list[1]),
-            e3: format!("{}", error_list[2]),
-        },
-    })
+    builder.build().get_address(cep).await
 }
 
 #[cfg(test)]
@@ -136,6 +70,7 @@ mod tests {
             neighborhood: "Zona Cívico-Administrativa".to_string(),
             city: "Brasília".to_string(),
             state: "DF".to_string(),
+            ..Default::default()
         };
 
         let recv_addr = super::get_address("70150903", None).await.unwrap();
@@ -154,6 +89,7 @@ mod tests {
             neighborhood: "Zona Cívico-Administrativa".to_string(),
             city: "Brasília".to_string(),
             state: "DF".to_string(),
+            ..Default::default()
         };
 
         let recv_addr = async_std::task::block_on(super::get_address("70150903", None)).unwrap();
@@ -172,11 +108,7 @@ mod tests {
     fn all_services_error() {
         let err = error::Error {
             source: error::Source::LagoinhaLib,
-            kind: error::Kind::AllServicesReturnedErrors {
-                e1: "".to_owned(),
-                e2: "".to_owned(),
-                e3: "".to_owned(),
-            },
+            kind: error::Kind::AllServicesReturnedErrors { errors: vec![] },
         };
 
         let recv_err = async_std::task::block_on(super::get_address("123", None)).unwrap_err();
@@ -188,11 +120,7 @@ mod tests {
     async fn all_services_error_tokio() {
         let err = error::Error {
             source: error::Source::LagoinhaLib,
-            kind: error::Kind::AllServicesReturnedErrors {
-                e1: "".to_owned(),
-                e2: "".to_owned(),
-                e3: "".to_owned(),
-            },
+            kind: error::Kind::AllServicesReturnedErrors { errors: vec![] },
         };
 
         let recv_err = super::get_address("123", None).await.unwrap_err();
@@ -0,0 +1,167 @@
+//! Merge combines the addresses returned by many providers into a single, richer address.
+//!
+//! # Example
+//! ```
+//!extern crate lagoinha;
+//!extern crate tokio;
+//!
+//!#[tokio::main]
+//!async fn main() {
+//!    let client = lagoinha::Lagoinha::default();
+//!    let merged = client.merge("70150903").await;
+//!    println!("{:#?}", merged);
+//!}
+//!```
+
+use crate::client::Response;
+use crate::services::Address;
+
+use std::collections::BTreeMap;
+
+/// Merged is an address where each field was filled by the best provider that returned it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merged {
+    /// address holds the merged fields
+    pub address: Address,
+    /// provenance maps each filled field to the name of the provider it came from. Empty fields are not listed.
+    pub provenance: BTreeMap<&'static str, String>,
+    /// providers lists the providers that answered, in the order they answered
+    pub providers: Vec<String>,
+}
+
+/// merge fills each field with the first non empty value, following the order of the providers in the pool.
+/// Providers that declare support for a field are preferred over the ones that do not.
+pub(crate) fn merge(responses: &[Response]) -> Option<Merged> {
+    if responses.is_empty() {
+        return None;
+    }
+    let mut by_priority: Vec<&Response> = responses.iter().collect();
+    by_priority.sort_by_key(|r| r.index);
+
+    let mut address = Address::default();
+    let mut provenance = BTreeMap::new();
+    for name in Address::FIELDS.iter() {
+        let supported = by_priority.iter().filter(|r| r.capabilities.supports(name));
+        let unsupported = by_priority
+            .iter()
+            .filter(|r| !r.capabilities.supports(name));
+
+        let best = supported.chain(unsupported).find_map(|r| {
+            r.address
+                .field(name)
+                .filter(|value| !value.is_empty())
+                .map(|value| (r, value))
+        });
+        if let (Some((response, value)), Some(field)) = (best, address.field_mut(name)) {
+            *field = value.to_owned();
+            provenance.insert(*name, response.provider.clone());
+        }
+    }
+
+    Some(Merged {
+        address,
+        provenance,
+        providers: responses.iter().map(|r| r.provider.clone()).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::merge;
+    use crate::client::Response;
+    use crate::services::{Address, Capabilities};
+
+    fn correios(index: usize) -> Response {
+        Response {
+            index,
+            provider: "correios".to_owned(),
+            capabilities: Capabilities::default(),
+            address: Address {
+                cep: "70150903".to_string(),
+                address: "SPP".to_string(),
+                neighborhood: "Zona Cívico-Administrativa".to_string(),
+                city: "Brasília".to_string(),
+                state: "DF".to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn viacep(index: usize) -> Response {
+        Response {
+            index,
+            provider: "viacep".to_owned(),
+            capabilities: Capabilities {
+                details: true,
+                ibge: true,
+                gia: true,
+                unidade: true,
+            },
+            address: Address {
+                cep: "70150-903".to_string(),
+                address: "SPP".to_string(),
+                neighborhood: "Zona Cívico-Administrativa".to_string(),
+                city: "Brasília".to_string(),
+                state: "DF".to_string(),
+                ibge: "5300108".to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn cepla(index: usize) -> Response {
+        Response {
+            index,
+            provider: "cepla".to_owned(),
+            capabilities: Capabilities {
+                details: true,
+                ..Default::default()
+            },
+            address: Address {
+                cep: "70150903".to_string(),
+                address: "SPP".to_string(),
+                details: "Palácio da Alvorada (Residência Oficial do Presidente da República)"
+                    .to_string(),
+                neighborhood: "Zona Cívico-Administrativa".to_string(),
+                city: "Brasília".to_string(),
+                state: "DF".to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn merge_fills_from_every_provider() {
+        // responses are in arrival order, while the index is the order in the pool
+        let responses = vec![cepla(2), correios(0), viacep(1)];
+        let merged = merge(&responses).unwrap();
+
+        assert_eq!(merged.address.cep, "70150903");
+        assert_eq!(merged.provenance["cep"], "correios");
+        assert_eq!(merged.address.ibge, "5300108");
+        assert_eq!(merged.provenance["ibge"], "viacep");
+        assert_eq!(
+            merged.address.details,
+            "Palácio da Alvorada (Residência Oficial do Presidente da República)"
+        );
+        // viacep supports details, but returned it empty
+        assert_eq!(merged.provenance["details"], "cepla");
+        assert!(merged.address.gia.is_empty());
+        assert!(!merged.provenance.contains_key("gia"));
+        assert_eq!(merged.providers, vec!["cepla", "correios", "viacep"]);
+    }
+
+    #[test]
+    fn merge_prefers_supported_fields() {
+        let mut unsupported = correios(0);
+        unsupported.address.details = "guessed".to_string();
+        let responses = vec![unsupported, cepla(1)];
+        let merged = merge(&responses).unwrap();
+        assert_eq!(merged.provenance["details"], "cepla");
+    }
+
+    #[test]
+    fn merge_empty() {
+        assert!(merge(&[]).is_none());
+    }
+}
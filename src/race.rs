@@ -0,0 +1,258 @@
+//! Combinators used by the client to run requests to many providers at once.
+
+use async_std::future::timeout;
+use async_std::task;
+use futures::future::{self, Either, FutureExt};
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures::Future;
+use std::time::Duration;
+
+/// first_success polls all futures concurrently and returns the first successful result.
+/// The remaining futures are cancelled by dropping them.
+/// If every future fails, all the errors are returned in the order they arrived.
+pub(crate) async fn first_success<I, T, E>(futures: I) -> Result<T, Vec<E>>
+where
+    I: IntoIterator,
+    I::Item: Future<Output = Result<T, E>>,
+{
+    let mut pending: FuturesUnordered<_> = futures.into_iter().collect();
+    let mut errors = Vec::with_capacity(pending.len());
+
+    while let Some(result) = pending.next().await {
+        match result {
+            Ok(value) => return Ok(value),
+            Err(e) => errors.push(e),
+        }
+    }
+    Err(errors)
+}
+
+/// fallback starts the futures one at a time, in order, moving to the next one when the current one fails,
+/// and returns the first successful result.
+/// With a hedge delay, the next future is also started whenever no result arrived within the delay,
+/// and the ones already running keep going. The remaining futures are cancelled by dropping them.
+/// If every future fails, the errors are returned in the order they arrived.
+pub(crate) async fn fallback<F, Fut, T, E>(
+    count: usize,
+    mut start: F,
+    hedge: Option<Duration>,
+) -> Result<T, Vec<E>>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut pending = FuturesUnordered::new();
+    let mut errors = Vec::with_capacity(count);
+    let mut next = 0;
+
+    loop {
+        if pending.is_empty() {
+            if next == count {
+                return Err(errors);
+            }
+            pending.push(start(next));
+            next += 1;
+        }
+
+        let completed = match hedge {
+            Some(delay) if next < count => {
+                let hedge_timer = task::sleep(delay).boxed();
+                match future::select(pending.next(), hedge_timer).await {
+                    Either::Left((completed, _)) => completed,
+                    Either::Right(_) => None,
+                }
+            }
+            _ => pending.next().await,
+        };
+
+        match completed {
+            Some(Ok(value)) => return Ok(value),
+            Some(Err(e)) => {
+                errors.push(e);
+                if next < count {
+                    pending.push(start(next));
+                    next += 1;
+                }
+            }
+            // the hedge delay passed without a result
+            None => {
+                pending.push(start(next));
+                next += 1;
+            }
+        }
+    }
+}
+
+/// collect polls all futures concurrently until `wanted` of them succeed, every future finishes, or the deadline passes.
+/// The remaining futures are cancelled by dropping them.
+/// Successes and errors are returned in the order they arrived.
+pub(crate) async fn collect<I, T, E>(
+    futures: I,
+    wanted: usize,
+    deadline: Option<Duration>,
+) -> (Vec<T>, Vec<E>)
+where
+    I: IntoIterator,
+    I::Item: Future<Output = Result<T, E>>,
+{
+    let mut pending: FuturesUnordered<_> = futures.into_iter().collect();
+    let mut successes = Vec::with_capacity(wanted.min(pending.len()));
+    let mut errors = Vec::new();
+
+    let gather = async {
+        while successes.len() < wanted {
+            match pending.next().await {
+                Some(Ok(value)) => successes.push(value),
+                Some(Err(e)) => errors.push(e),
+                None => break,
+            }
+        }
+    };
+    match deadline {
+        Some(deadline) => timeout(deadline, gather).await.unwrap_or(()),
+        None => gather.await,
+    }
+    (successes, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collect, fallback, first_success};
+    use futures::channel::oneshot;
+    use futures::future::{self, FutureExt};
+    use std::cell::RefCell;
+    use std::time::Duration;
+
+    #[test]
+    fn first_success_skips_earlier_errors() {
+        let (tx, rx) = oneshot::channel::<Result<u8, &str>>();
+        // the success can only arrive after the error was returned, which must not end the race
+        let fail_then_release = async move {
+            tx.send(Ok(1)).unwrap();
+            Err("first")
+        };
+        let futures = vec![rx.map(|r| r.unwrap()).boxed(), fail_then_release.boxed()];
+        assert_eq!(async_std::task::block_on(first_success(futures)), Ok(1));
+    }
+
+    #[test]
+    fn first_success_collects_every_error() {
+        let futures = vec![
+            future::ready(Err::<u8, _>("a")),
+            future::ready(Err("b")),
+            future::ready(Err("c")),
+        ];
+        let result = async_std::task::block_on(first_success(futures));
+        assert_eq!(result, Err(vec!["a", "b", "c"]));
+    }
+
+    #[test]
+    fn first_success_cancels_losers() {
+        let (tx, rx) = oneshot::channel::<Result<u8, &str>>();
+        let futures = vec![
+            rx.map(|r| r.unwrap_or(Err("cancelled"))).boxed(),
+            future::ready(Ok(2)).boxed(),
+        ];
+        assert_eq!(async_std::task::block_on(first_success(futures)), Ok(2));
+        // the pending future was dropped, so its receiver is gone
+        assert!(tx.is_canceled());
+    }
+
+    #[test]
+    fn fallback_moves_to_the_next_on_failure() {
+        let started = RefCell::new(vec![]);
+        let results = [Err("a"), Ok(1), Ok(2)];
+        let result = async_std::task::block_on(fallback(
+            results.len(),
+            |i| {
+                started.borrow_mut().push(i);
+                future::ready(results[i])
+            },
+            None,
+        ));
+        assert_eq!(result, Ok(1));
+        assert_eq!(*started.borrow(), vec![0, 1]);
+    }
+
+    #[test]
+    fn fallback_collects_every_error() {
+        let results = [Err::<u8, _>("a"), Err("b")];
+        let result =
+            async_std::task::block_on(fallback(results.len(), |i| future::ready(results[i]), None));
+        assert_eq!(result, Err(vec!["a", "b"]));
+    }
+
+    #[test]
+    fn fallback_without_hedge_waits() {
+        let started = RefCell::new(vec![]);
+        let result = async_std::task::block_on(fallback(
+            2,
+            |i| {
+                started.borrow_mut().push(i);
+                async move {
+                    async_std::task::sleep(Duration::from_millis(50)).await;
+                    Ok::<_, &str>(i)
+                }
+            },
+            None,
+        ));
+        assert_eq!(result, Ok(0));
+        assert_eq!(*started.borrow(), vec![0]);
+    }
+
+    #[test]
+    fn fallback_hedges_slow_futures() {
+        let (tx, rx) = oneshot::channel::<Result<u8, &str>>();
+        let mut slow = Some(rx.map(|r| r.unwrap_or(Err("cancelled"))).boxed());
+        let started = RefCell::new(vec![]);
+        let result = async_std::task::block_on(fallback(
+            3,
+            |i| {
+                started.borrow_mut().push(i);
+                match i {
+                    // the first never answers by itself, so only the hedge can move on
+                    0 => slow.take().unwrap(),
+                    i => future::ready(Ok(i as u8)).boxed(),
+                }
+            },
+            Some(Duration::from_millis(10)),
+        ));
+        assert_eq!(result, Ok(1));
+        assert_eq!(*started.borrow(), vec![0, 1]);
+        assert!(tx.is_canceled());
+    }
+
+    #[test]
+    fn collect_stops_at_wanted() {
+        let (tx, rx) = oneshot::channel::<Result<u8, &str>>();
+        let futures = vec![
+            future::ready(Ok(1)).boxed(),
+            future::ready(Err("a")).boxed(),
+            future::ready(Ok(2)).boxed(),
+            rx.map(|r| r.unwrap_or(Err("cancelled"))).boxed(),
+        ];
+        let (successes, errors) = async_std::task::block_on(collect(futures, 2, None));
+        assert_eq!(successes, vec![1, 2]);
+        assert_eq!(errors, vec!["a"]);
+        assert!(tx.is_canceled());
+    }
+
+    #[test]
+    fn collect_keeps_partial_results_at_deadline() {
+        let futures = vec![
+            future::ready(Ok::<u8, &str>(1)).boxed(),
+            future::pending().boxed(),
+        ];
+        let deadline = Some(std::time::Duration::from_millis(20));
+        let (successes, errors) = async_std::task::block_on(collect(futures, 2, deadline));
+        assert_eq!(successes, vec![1]);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn first_success_empty() {
+        let futures: Vec<future::Ready<Result<u8, &str>>> = vec![];
+        let result = async_std::task::block_on(first_success(futures));
+        assert_eq!(result, Err(vec![]));
+    }
+}
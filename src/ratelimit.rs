@@ -0,0 +1,218 @@
+//! Ratelimit keeps the requests to each provider within a budget: a token bucket, and an optional daily quota.
+//!
+//! # Example
+//! ```
+//!extern crate lagoinha;
+//!extern crate tokio;
+//!
+//!use lagoinha::ratelimit::RateLimit;
+//!use std::time::Duration;
+//!
+//!#[tokio::main]
+//!async fn main() {
+//!    let client = lagoinha::Lagoinha::builder()
+//!        .rate_limit(RateLimit::new(5, Duration::from_secs(1)))
+//!        .provider_rate_limit("viacep", RateLimit::new(2, Duration::from_secs(1)).daily_quota(10_000))
+//!        .build();
+//!    let addr = client.get_address("70150903").await;
+//!    println!("{:#?}", addr);
+//!}
+//!```
+
+use async_std::task;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// RateLimit sets the request budget of a provider.
+/// The token bucket allows `burst` requests at once, and refills at the configured rate.
+/// The daily quota counts the requests made in the current UTC day.
+/// A request over the rate either waits for a token, which is the default, or skips the provider.
+/// A request over the daily quota always skips the provider.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// rate is the number of tokens added per second, or None for no rate limit
+    rate: Option<f64>,
+    burst: u32,
+    daily_quota: Option<u64>,
+    wait: bool,
+}
+
+impl RateLimit {
+    /// new returns a limit of `requests` requests for every `per` interval, allowing all of them at once
+    pub fn new(requests: u32, per: Duration) -> Self {
+        let requests = requests.max(1);
+        RateLimit {
+            rate: Some(f64::from(requests) / per.as_secs_f64().max(f64::EPSILON)),
+            burst: requests,
+            daily_quota: None,
+            wait: true,
+        }
+    }
+
+    /// unlimited returns a limit without a rate, to be used with a daily quota only
+    pub fn unlimited() -> Self {
+        RateLimit {
+            rate: None,
+            burst: 1,
+            daily_quota: None,
+            wait: true,
+        }
+    }
+
+    /// burst sets how many requests can be sent at once, after a quiet period
+    pub fn burst(mut self, requests: u32) -> Self {
+        self.burst = requests.max(1);
+        self
+    }
+
+    /// daily_quota sets the maximum number of requests in a UTC day
+    pub fn daily_quota(mut self, requests: u64) -> Self {
+        self.daily_quota = Some(requests);
+        self
+    }
+
+    /// wait sets whether a request over the rate waits for a token, or skips the provider. It waits by default.
+    pub fn wait(mut self, wait: bool) -> Self {
+        self.wait = wait;
+        self
+    }
+}
+
+/// Exceeded is the reason a request was refused by a Limiter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Exceeded {
+    Rate,
+    Quota(u64),
+}
+
+/// Refusal is the reason a token could not be taken right away
+enum Refusal {
+    /// Wait holds the time until the next token
+    Wait(Duration),
+    Quota(u64),
+}
+
+struct State {
+    tokens: f64,
+    updated: Instant,
+    /// day is the current UTC day, counted from the Unix epoch
+    day: u64,
+    used: u64,
+}
+
+/// Limiter enforces the RateLimit of a single provider
+pub(crate) struct Limiter {
+    limit: RateLimit,
+    state: Mutex<State>,
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() / 86_400)
+}
+
+impl Limiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Limiter {
+            limit,
+            state: Mutex::new(State {
+                tokens: f64::from(limit.burst),
+                updated: Instant::now(),
+                day: today(),
+                used: 0,
+            }),
+        }
+    }
+
+    /// try_acquire takes a token and a unit of the quota. If there is no token, it returns how long until there is one.
+    fn try_acquire(&self) -> Result<(), Refusal> {
+        let mut state = self.state.lock().unwrap();
+        let day = today();
+        if state.day != day {
+            state.day = day;
+            state.used = 0;
+        }
+        if let Some(quota) = self.limit.daily_quota {
+            if state.used >= quota {
+                return Err(Refusal::Quota(quota));
+            }
+        }
+        if let Some(rate) = self.limit.rate {
+            let now = Instant::now();
+            let refill = now.duration_since(state.updated).as_secs_f64() * rate;
+            state.tokens = (state.tokens + refill).min(f64::from(self.limit.burst));
+            state.updated = now;
+            if state.tokens < 1.0 {
+                return Err(Refusal::Wait(Duration::from_secs_f64(
+                    (1.0 - state.tokens) / rate,
+                )));
+            }
+            state.tokens -= 1.0;
+        }
+        state.used += 1;
+        Ok(())
+    }
+
+    /// acquire waits until the provider can be called, or fails if it is over its budget and must be skipped
+    pub(crate) async fn acquire(&self) -> Result<(), Exceeded> {
+        loop {
+            match self.try_acquire() {
+                Ok(()) => return Ok(()),
+                Err(Refusal::Wait(wait)) if self.limit.wait => task::sleep(wait).await,
+                Err(Refusal::Wait(_)) => return Err(Exceeded::Rate),
+                Err(Refusal::Quota(quota)) => return Err(Exceeded::Quota(quota)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Exceeded, Limiter, RateLimit};
+    use async_std::task::block_on;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn burst_then_wait() {
+        let limiter = Limiter::new(RateLimit::new(2, Duration::from_millis(100)));
+        let start = Instant::now();
+        block_on(async {
+            for _ in 0..4 {
+                limiter.acquire().await.unwrap();
+            }
+        });
+        // 2 at once, then one every 50ms
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[test]
+    fn skip_instead_of_waiting() {
+        let limit = RateLimit::new(1, Duration::from_secs(60)).wait(false);
+        let limiter = Limiter::new(limit);
+        assert_eq!(block_on(limiter.acquire()), Ok(()));
+        assert_eq!(block_on(limiter.acquire()), Err(Exceeded::Rate));
+    }
+
+    #[test]
+    fn quota_is_exhausted_and_reset_daily() {
+        let limiter = Limiter::new(RateLimit::unlimited().daily_quota(2));
+        assert_eq!(block_on(limiter.acquire()), Ok(()));
+        assert_eq!(block_on(limiter.acquire()), Ok(()));
+        assert_eq!(block_on(limiter.acquire()), Err(Exceeded::Quota(2)));
+
+        limiter.state.lock().unwrap().day -= 1;
+        assert_eq!(block_on(limiter.acquire()), Ok(()));
+    }
+
+    #[test]
+    fn requests_skipped_by_the_rate_do_not_use_the_quota() {
+        let limit = RateLimit::new(1, Duration::from_secs(60))
+            .wait(false)
+            .daily_quota(5);
+        let limiter = Limiter::new(limit);
+        block_on(limiter.acquire()).unwrap();
+        assert!(block_on(limiter.acquire()).is_err());
+        assert_eq!(limiter.state.lock().unwrap().used, 1);
+    }
+}
@@ -0,0 +1,166 @@
+//! Report holds the detailed result of a lookup: which provider answered, and how every attempt went.
+//!
+//! # Example
+//! ```
+//!extern crate lagoinha;
+//!extern crate tokio;
+//!
+//!#[tokio::main]
+//!async fn main() {
+//!    let client = lagoinha::Lagoinha::default();
+//!    if let Ok(lookup) = client.lookup("70150903").await {
+//!        println!("{} answered in {:?}", lookup.source, lookup.latency);
+//!        for attempt in lookup.attempts {
+//!            println!("{}: {:?} after {:?}", attempt.provider, attempt.outcome, attempt.latency);
+//!        }
+//!    }
+//!}
+//!```
+
+use crate::error::{Error, Kind, Source};
+use crate::services::{Address, CepProvider};
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Lookup is the address returned by a provider, with the details of every provider attempt
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lookup {
+    pub address: Address,
+    /// provider is the name of the provider that supplied the address
+    pub provider: String,
+    /// source identifies the provider that supplied the address
+    pub source: Source,
+    /// latency is the time the winning provider took to answer
+    pub latency: Duration,
+    /// attempts lists every provider that was called, in the order they were called, including the winner
+    pub attempts: Vec<Attempt>,
+    /// cached indicates that the address was served by the client cache. No provider was called, and attempts is empty.
+    pub cached: bool,
+    /// stale indicates that the address came from an expired cache entry, served while it is refreshed or because every provider failed
+    pub stale: bool,
+}
+
+/// Attempt is a call to a provider during a lookup
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attempt {
+    pub provider: String,
+    pub source: Source,
+    pub outcome: Outcome,
+    /// latency is the time from the start of the call until it finished or was cancelled
+    pub latency: Duration,
+}
+
+/// Outcome is how a provider attempt ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Success indicates that the provider returned an address
+    Success,
+    /// Error holds the error returned by the provider
+    Error(Error),
+    /// Cancelled indicates that the call was dropped before it finished, because another provider answered first or the deadline passed
+    Cancelled,
+    /// TimedOut indicates that the provider did not answer within its request timeout
+    TimedOut,
+}
+
+/// Recorder collects the attempts of a lookup
+#[derive(Default)]
+pub(crate) struct Recorder {
+    attempts: Mutex<Vec<Attempt>>,
+}
+
+impl Recorder {
+    /// start records a new attempt as cancelled, until it is finished
+    pub(crate) fn start(&self, provider: &dyn CepProvider) -> AttemptGuard<'_> {
+        let mut attempts = self.attempts.lock().unwrap();
+        attempts.push(Attempt {
+            provider: provider.name().to_owned(),
+            source: provider.source(),
+            outcome: Outcome::Cancelled,
+            latency: Duration::from_secs(0),
+        });
+        AttemptGuard {
+            recorder: self,
+            index: attempts.len() - 1,
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn into_attempts(self) -> Vec<Attempt> {
+        self.attempts.into_inner().unwrap()
+    }
+}
+
+/// AttemptGuard updates its attempt when finished. If it is dropped before that, the attempt stays cancelled.
+pub(crate) struct AttemptGuard<'a> {
+    recorder: &'a Recorder,
+    index: usize,
+    start: Instant,
+}
+
+impl AttemptGuard<'_> {
+    /// finish records the outcome of the attempt, and returns its index
+    pub(crate) fn finish(self, result: &Result<Address, Error>) -> usize {
+        let outcome = match result {
+            Ok(_) => Outcome::Success,
+            Err(Error {
+                kind: Kind::Timeout { .. },
+                ..
+            }) => Outcome::TimedOut,
+            Err(e) => Outcome::Error(e.clone()),
+        };
+        self.recorder.attempts.lock().unwrap()[self.index].outcome = outcome;
+        self.index
+    }
+}
+
+impl Drop for AttemptGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut attempts) = self.recorder.attempts.lock() {
+            attempts[self.index].latency = self.start.elapsed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Outcome, Recorder};
+    use crate::error::{Error, Kind, Source};
+    use crate::services::viacep::ViacepProvider;
+    use crate::services::Address;
+    use std::time::Duration;
+
+    #[test]
+    fn recorder_outcomes() {
+        let recorder = Recorder::default();
+        let success = recorder.start(&ViacepProvider);
+        let error = recorder.start(&ViacepProvider);
+        let timeout = recorder.start(&ViacepProvider);
+        let cancelled = recorder.start(&ViacepProvider);
+
+        assert_eq!(success.finish(&Ok(Address::default())), 0);
+        let err = Error {
+            source: Source::Viacep,
+            kind: Kind::ServerError { code: 500 },
+        };
+        error.finish(&Err(err.clone()));
+        timeout.finish(&Err(Error {
+            source: Source::Viacep,
+            kind: Kind::Timeout {
+                provider: "viacep".to_owned(),
+                after: Duration::from_secs(1),
+            },
+        }));
+        drop(cancelled);
+
+        let attempts = recorder.into_attempts();
+        assert_eq!(attempts.len(), 4);
+        assert_eq!(attempts[0].provider, "viacep");
+        assert_eq!(attempts[0].source, Source::Viacep);
+        assert_eq!(attempts[0].outcome, Outcome::Success);
+        assert_eq!(attempts[1].outcome, Outcome::Error(err));
+        assert_eq!(attempts[2].outcome, Outcome::TimedOut);
+        assert_eq!(attempts[3].outcome, Outcome::Cancelled);
+    }
+}
@@ -0,0 +1,222 @@
+//! Retry sets how a provider call is repeated after a transient failure, like a 5xx status or a timeout.
+//!
+//! # Example
+//! ```
+//!extern crate lagoinha;
+//!extern crate tokio;
+//!
+//!use lagoinha::retry::RetryPolicy;
+//!use std::time::Duration;
+//!
+//!#[tokio::main]
+//!async fn main() {
+//!    let client = lagoinha::Lagoinha::builder()
+//!        .retry(RetryPolicy::new(3).backoff(Duration::from_millis(200), Duration::from_secs(2)))
+//!        .provider_retry("correios", RetryPolicy::none())
+//!        .build();
+//!    let addr = client.get_address("70150903").await;
+//!    println!("{:#?}", addr);
+//!}
+//!```
+
+use crate::error::{Error, Kind};
+
+use std::time::Duration;
+
+/// DEFAULT_BASE_DELAY is the wait before the first retry, unless configured otherwise
+pub const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// DEFAULT_MAX_DELAY is the longest wait between retries computed by the backoff, unless configured otherwise
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// RetryPolicy sets how many times a provider is called for a single lookup, and how long to wait between the calls.
+/// The wait doubles after every retry, up to a maximum. With jitter, a random part of the wait is dropped,
+/// so that many clients do not retry in lockstep.
+/// A Retry-After header on a 429 or 503 response is honoured when it asks for a longer wait.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    retryable: fn(&Error) -> bool,
+}
+
+impl Default for RetryPolicy {
+    /// default calls each provider once, without retries
+    fn default() -> Self {
+        RetryPolicy::none()
+    }
+}
+
+impl RetryPolicy {
+    /// new returns a policy that calls a provider up to `max_attempts` times, counting the first call,
+    /// with the default backoff, jitter, and the errors accepted by default_retryable
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            base_delay: DEFAULT_BASE_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+            jitter: true,
+            retryable: default_retryable,
+        }
+    }
+
+    /// none returns a policy that never retries
+    pub fn none() -> Self {
+        RetryPolicy::new(1)
+    }
+
+    /// backoff sets the wait before the first retry, and the longest wait between retries
+    pub fn backoff(mut self, base_delay: Duration, max_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self.max_delay = max_delay.max(base_delay);
+        self
+    }
+
+    /// jitter enables or disables the random reduction of the waits. It is enabled by default.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// retry_on sets which errors are worth a retry. It defaults to default_retryable.
+    pub fn retry_on(mut self, retryable: fn(&Error) -> bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    /// max_attempts returns the maximum number of calls to a provider, counting the first one
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// should_retry indicates whether a call that failed with `error`, after `attempts` calls, must be repeated
+    pub(crate) fn should_retry(&self, attempts: u32, error: &Error) -> bool {
+        attempts < self.max_attempts && (self.retryable)(error)
+    }
+
+    /// delay returns the wait before the given retry, counting from 1
+    pub(crate) fn delay(&self, retry: u32, error: &Error) -> Duration {
+        let factor = 1u32
+            .checked_shl(retry.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        let exponential = self
+            .base_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        let backoff = if self.jitter {
+            let half = exponential / 2;
+            half + Duration::from_nanos(fastrand::u64(0..=half.as_nanos() as u64))
+        } else {
+            exponential
+        };
+        match error.kind {
+            Kind::Throttled { retry_after, .. } => backoff.max(retry_after),
+            _ => backoff,
+        }
+    }
+}
+
+/// default_retryable accepts the errors that are likely to go away on their own, as classified by Error::is_retryable
+pub fn default_retryable(error: &Error) -> bool {
+    error.is_retryable()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_retryable, RetryPolicy};
+    use crate::error::{Cause, Error, Kind, Source};
+    use std::time::Duration;
+
+    fn error(kind: Kind) -> Error {
+        Error {
+            source: Source::Viacep,
+            kind,
+        }
+    }
+
+    #[test]
+    fn exponential_backoff_is_capped() {
+        let policy = RetryPolicy::new(10)
+            .backoff(Duration::from_millis(100), Duration::from_millis(500))
+            .jitter(false);
+        let err = error(Kind::ServerError { code: 500 });
+        let delays: Vec<u128> = (1..=5)
+            .map(|retry| policy.delay(retry, &err).as_millis())
+            .collect();
+        assert_eq!(delays, vec![100, 200, 400, 500, 500]);
+        assert_eq!(policy.delay(u32::MAX, &err), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn jitter_stays_within_half_of_the_backoff() {
+        let policy =
+            RetryPolicy::new(3).backoff(Duration::from_millis(100), Duration::from_secs(1));
+        let err = error(Kind::ServerError { code: 500 });
+        for _ in 0..100 {
+            let delay = policy.delay(2, &err);
+            assert!(delay >= Duration::from_millis(100));
+            assert!(delay <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn retry_after_is_honoured() {
+        let policy = RetryPolicy::new(3).jitter(false);
+        let throttled = error(Kind::Throttled {
+            code: 429,
+            retry_after: Duration::from_secs(2),
+        });
+        assert_eq!(policy.delay(1, &throttled), Duration::from_secs(2));
+
+        // a shorter Retry-After does not cut the backoff
+        let policy = policy.backoff(Duration::from_secs(3), Duration::from_secs(3));
+        assert_eq!(policy.delay(1, &throttled), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn should_retry_stops_at_max_attempts() {
+        let policy = RetryPolicy::new(3);
+        let err = error(Kind::ServerError { code: 502 });
+        assert!(policy.should_retry(1, &err));
+        assert!(policy.should_retry(2, &err));
+        assert!(!policy.should_retry(3, &err));
+        assert!(!RetryPolicy::none().should_retry(1, &err));
+        assert_eq!(RetryPolicy::new(0).max_attempts(), 1);
+    }
+
+    #[test]
+    fn should_retry_only_retryable_errors() {
+        let policy = RetryPolicy::new(3);
+        assert!(!policy.should_retry(1, &error(Kind::ClientError { code: 404 })));
+        assert!(!policy.should_retry(1, &error(Kind::InputError)));
+
+        let only_client_errors = policy.retry_on(|e| matches!(e.kind, Kind::ClientError { .. }));
+        assert!(only_client_errors.should_retry(1, &error(Kind::ClientError { code: 404 })));
+        assert!(!only_client_errors.should_retry(1, &error(Kind::ServerError { code: 500 })));
+    }
+
+    #[test]
+    fn default_retryable_kinds() {
+        assert!(default_retryable(&error(Kind::ServerError { code: 503 })));
+        assert!(default_retryable(&error(Kind::ClientError { code: 429 })));
+        assert!(default_retryable(&error(Kind::ClientError { code: 408 })));
+        assert!(default_retryable(&error(Kind::MissingBodyError)));
+        assert!(default_retryable(&error(Kind::Network {
+            cause: Cause::new(std::io::Error::from(std::io::ErrorKind::ConnectionRefused)),
+        })));
+        assert!(default_retryable(&error(Kind::Timeout {
+            provider: "viacep".to_owned(),
+            after: Duration::from_secs(1),
+        })));
+        assert!(!default_retryable(&error(Kind::ClientError { code: 400 })));
+        assert!(!default_retryable(&error(Kind::BodyParsingError {
+            error: "".to_owned(),
+            body: "".to_owned(),
+        })));
+        assert!(!default_retryable(&error(Kind::UnknownServerError {
+            code: 302
+        })));
+    }
+}
@@ -7,11 +7,15 @@
 
 use crate::error::Error;
 use crate::error::Kind;
+use crate::error::Source;
 use crate::error::Source::Cepla;
 
+use crate::services::{self, Addressable, Capabilities, CepProvider};
+
+use futures::future::{BoxFuture, FutureExt};
 use serde::{Deserialize, Serialize};
 
-use isahc::{config::Configurable, ReadResponseExt, Request, RequestExt};
+use isahc::{config::Configurable, AsyncReadResponseExt, Request, RequestExt};
 
 /// request function runs the API call to cepla service
 pub async fn request(cep: &str) -> Result<Address, Error> {
@@ -25,58 +29,66 @@ pub async fn request(cep: &str) -> Result<Address, Error> {
             source: Cepla,
         }))?;
 
-    let mut response = req.send().or(Err(Error {
+    let mut response = req
+        .send_async()
+        .await
+        .map_err(|e| services::transport_error(e, Cepla))?;
+
+    services::check_status(&response, Cepla)?;
+
+    let body = response.text().await.or(Err(Error {
         kind: Kind::MissingBodyError,
         source: Cepla,
     }))?;
 
-    match response.status().as_u16() {
-        200..=299 => (),
-        400..=499 => {
-            return Err(Error {
-                kind: Kind::ClientError {
-                    code: response.status().as_u16(),
-                },
-                source: Cepla,
-            });
-        }
-        500..=599 => {
-            return Err(Error {
-                kind: Kind::ServerError {
-                    code: response.status().as_u16(),
-                },
-                source: Cepla,
-            });
-        }
-        _ => {
-            return Err(Error {
-                kind: Kind::UnknownServerError {
-                    code: response.status().as_u16(),
-                },
-                source: Cepla,
-            });
-        }
+    if not_found(&body) {
+        return Err(Error {
+            kind: Kind::NotFound,
+            source: Cepla,
+        });
     }
-    let body = response.body_mut();
 
-    let address = serde_json::from_reader(body);
+    let address = serde_json::from_str(&body);
     match address {
-        Ok(address) => return Ok(address),
-        Err(e) => {
-            let str_body = response.text();
-            let str_body = match str_body {
-                Ok(str_body) => str_body,
-                Err(e) => "Failed to produce string body ".to_owned() + e.to_string().as_str(),
-            };
-            return Err(Error {
-                kind: Kind::BodyParsingError {
-                    error: e.to_string(),
-                    body: str_body.to_string(),
-                },
-                source: Cepla,
-            });
+        Ok(address) => Ok(address),
+        Err(e) => Err(Error {
+            kind: Kind::BodyParsingError {
+                error: e.to_string(),
+                body,
+            },
+            source: Cepla,
+        }),
+    }
+}
+
+/// not_found indicates whether the body is empty, as CepLá answers a CEP that does not exist without an address
+fn not_found(body: &str) -> bool {
+    matches!(body.trim(), "" | "[]" | "{}")
+}
+
+/// CeplaProvider is the CepLá service as a CepProvider, so it can be added to the pool of a Lagoinha client
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CeplaProvider;
+
+impl CepProvider for CeplaProvider {
+    fn name(&self) -> &str {
+        "cepla"
+    }
+
+    fn source(&self) -> Source {
+        Cepla
+    }
+
+    fn lookup<'a>(&'a self, cep: &'a str) -> BoxFuture<'a, Result<services::Address, Error>> {
+        async move { request(cep).await.map(|addr| addr.to_address()) }.boxed()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            details: true,
+            ..Default::default()
         }
-    };
+    }
 }
 
 /// Address struct used to deserialize the results from the cepla API
@@ -98,6 +110,13 @@ pub struct Address {
 
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn not_found_body() {
+        assert!(super::not_found("[]"));
+        assert!(super::not_found(" \n"));
+        assert!(!super::not_found(r#"{"cep":"70150903","uf":"DF"}"#));
+    }
+
     #[test]
     fn valid_cepla() {
         let resaddr = async_std::task::block_on(super::request("70150903")).unwrap();
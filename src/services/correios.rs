@@ -1,11 +1,16 @@
 //! Correios service: http://www.buscacep.correios.com.br/sistemas/buscacep/BuscaCepEndereco.cfm
 
-use isahc::{ReadResponseExt, Request, RequestExt};
+use isahc::{AsyncReadResponseExt, Request, RequestExt};
 
+use crate::consensus;
 use crate::error::Error;
 use crate::error::Kind;
+use crate::error::Source;
 use crate::error::Source::Correios;
 
+use crate::services::{self, Addressable, Capabilities, CepProvider};
+
+use futures::future::{BoxFuture, FutureExt};
 use serde::{Deserialize, Serialize};
 
 /// request function runs the API call to correios service
@@ -35,61 +40,75 @@ pub async fn request(cep: &str) -> Result<Address, Error> {
         source: Correios,
     }))?;
 
-    let mut response = req.send().or(Err(Error {
+    let mut response = req
+        .send_async()
+        .await
+        .map_err(|e| services::transport_error(e, Correios))?;
+
+    // errors usually come with a SOAP fault that explains them
+    if let Err(error) = services::check_status(&response, Correios) {
+        if let Kind::ServerError { code } | Kind::ClientError { code } = error.kind {
+            let body = response.text().await.unwrap_or_default();
+            if let Some(kind) = fault(code, &body) {
+                return Err(Error {
+                    kind,
+                    source: Correios,
+                });
+            }
+        }
+        return Err(error);
+    }
+
+    let body = response.text().await.or(Err(Error {
         kind: Kind::MissingBodyError,
         source: Correios,
     }))?;
 
-    match response.status().as_u16() {
-        200..=299 => (),
-        400..=499 => {
-            return Err(Error {
-                kind: Kind::ClientError {
-                    code: response.status().as_u16(),
-                },
-                source: Correios,
-            });
-        }
-        500..=599 => {
-            return Err(Error {
-                kind: Kind::ServerError {
-                    code: response.status().as_u16(),
-                },
-                source: Correios,
-            });
-        }
-        _ => {
-            return Err(Error {
-                kind: Kind::UnknownServerError {
-                    code: response.status().as_u16(),
-                },
-                source: Correios,
-            });
-        }
+    let correios_data: Result<BodyTag, serde_xml_rs::Error> = serde_xml_rs::from_str(&body);
+    match correios_data {
+        Ok(correios_data) => Ok(correios_data.body_tag.consult_tag.return_tag),
+        Err(e) => Err(Error {
+            kind: Kind::BodyParsingError {
+                error: e.to_string(),
+                body,
+            },
+            source: Correios,
+        }),
     }
+}
 
-    let body = response.body_mut();
+/// fault maps the SOAP fault in the body of an unsuccessful response to an error kind.
+/// A CEP that does not exist is NotFound, a malformed one is an InputError, and other faults keep their text.
+fn fault(code: u16, body: &str) -> Option<Kind> {
+    let envelope: FaultEnvelope = serde_xml_rs::from_str(body).ok()?;
+    let fault = envelope.body.fault.faultstring.trim().to_owned();
+    // ignores case and accents, as in "CEP INVÁLIDO"
+    let normalized = consensus::normalize("faultstring", &fault);
+    Some(if normalized.contains("nao encontrado") {
+        Kind::NotFound
+    } else if normalized.contains("invalido") {
+        Kind::InputError
+    } else {
+        Kind::SoapFault { code, fault }
+    })
+}
 
-    let correios_data: Result<BodyTag, serde_xml_rs::Error> = serde_xml_rs::from_reader(body);
-    match correios_data {
-        Ok(correios_data) => {
-            return Ok(correios_data.body_tag.consult_tag.return_tag);
-        }
-        Err(e) => {
-            let str_body = response.text();
-            let str_body = match str_body {
-                Ok(str_body) => str_body,
-                Err(_) => "Failed to produce string body ".to_owned() + e.to_string().as_str(),
-            };
-            return Err(Error {
-                kind: Kind::BodyParsingError {
-                    error: e.to_string(),
-                    body: str_body.to_string(),
-                },
-                source: Correios,
-            });
-        }
-    };
+#[derive(Deserialize, Debug)]
+struct FaultEnvelope {
+    #[serde(rename = "Body")]
+    pub body: FaultBody,
+}
+
+#[derive(Deserialize, Debug)]
+struct FaultBody {
+    #[serde(rename = "Fault")]
+    pub fault: Fault,
+}
+
+#[derive(Deserialize, Debug)]
+struct Fault {
+    #[serde(default = "String::new")]
+    pub faultstring: String,
 }
 
 // these structs are used to define the entire path to the XML. There must be a better way to do this...
@@ -112,6 +131,28 @@ struct ReturnTag {
     pub return_tag: Address,
 }
 
+/// CorreiosProvider is the Correios service as a CepProvider, so it can be added to the pool of a Lagoinha client
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CorreiosProvider;
+
+impl CepProvider for CorreiosProvider {
+    fn name(&self) -> &str {
+        "correios"
+    }
+
+    fn source(&self) -> Source {
+        Correios
+    }
+
+    fn lookup<'a>(&'a self, cep: &'a str) -> BoxFuture<'a, Result<services::Address, Error>> {
+        async move { request(cep).await.map(|addr| addr.to_address()) }.boxed()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+}
+
 /// Address struct used to deserialize the results from the correios API
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Address {
@@ -129,6 +170,34 @@ pub struct Address {
 
 #[cfg(test)]
 mod tests {
+    fn soap_fault(faultstring: &str) -> String {
+        format!(
+            r#"<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/"><soap:Body><soap:Fault><faultcode>soap:Server</faultcode><faultstring>{0}</faultstring><detail><ns2:SigepClienteException xmlns:ns2="http://cliente.bean.master.sigep.bsb.correios.com.br/">{0}</ns2:SigepClienteException></detail></soap:Fault></soap:Body></soap:Envelope>"#,
+            faultstring
+        )
+    }
+
+    #[test]
+    fn faults() {
+        assert_eq!(
+            super::fault(500, &soap_fault("CEP NAO ENCONTRADO")),
+            Some(Kind::NotFound)
+        );
+        assert_eq!(
+            super::fault(500, &soap_fault("CEP INVÁLIDO")),
+            Some(Kind::InputError)
+        );
+        assert_eq!(
+            super::fault(500, &soap_fault("Sistema indisponível ")),
+            Some(Kind::SoapFault {
+                code: 500,
+                fault: "Sistema indisponível".to_owned()
+            })
+        );
+        assert_eq!(super::fault(502, "<html>Bad Gateway</html>"), None);
+        assert_eq!(super::fault(500, ""), None);
+    }
+
     #[test]
     fn valid_correios() {
         let resaddr = async_std::task::block_on(super::request("70150903")).unwrap();
@@ -180,7 +249,7 @@ mod tests {
                     err,
                     Error {
                         source: Source::Correios,
-                        kind: Kind::ServerError { code: 500 }
+                        kind: Kind::InputError
                     }
                 )
             })
@@ -3,10 +3,15 @@ pub mod correios;
 pub mod viacep;
 
 extern crate serde;
+use crate::error::{Cause, Error, Kind, Source};
+use futures::future::BoxFuture;
+use isahc::http::{header, Response};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 /// Address struct is the unified response for this package. All other services have a conversion function to it.
-#[derive(Deserialize, Serialize, Debug)]
+/// The ibge, gia and unidade fields are only returned by some services, and are empty otherwise.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
 pub struct Address {
     pub cep: String,
     pub address: String,
@@ -14,6 +19,110 @@ pub struct Address {
     pub neighborhood: String,
     pub state: String,
     pub city: String,
+    /// ibge is the city code from the Brazilian Institute of Geography and Statistics
+    #[serde(default)]
+    pub ibge: String,
+    /// gia is the state tax code (Guia de Informação e Apuração do ICMS), only used in São Paulo
+    #[serde(default)]
+    pub gia: String,
+    /// unidade is the name of the unit, for CEPs that belong to a single building or organization
+    #[serde(default)]
+    pub unidade: String,
+}
+
+impl Address {
+    /// FIELDS lists the names of the fields of the unified Address
+    pub const FIELDS: [&'static str; 9] = [
+        "cep",
+        "address",
+        "details",
+        "neighborhood",
+        "state",
+        "city",
+        "ibge",
+        "gia",
+        "unidade",
+    ];
+
+    /// field returns the value of the named field, or None if there is no field with this name
+    pub fn field(&self, name: &str) -> Option<&str> {
+        match name {
+            "cep" => Some(&self.cep),
+            "address" => Some(&self.address),
+            "details" => Some(&self.details),
+            "neighborhood" => Some(&self.neighborhood),
+            "state" => Some(&self.state),
+            "city" => Some(&self.city),
+            "ibge" => Some(&self.ibge),
+            "gia" => Some(&self.gia),
+            "unidade" => Some(&self.unidade),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn field_mut(&mut self, name: &str) -> Option<&mut String> {
+        match name {
+            "cep" => Some(&mut self.cep),
+            "address" => Some(&mut self.address),
+            "details" => Some(&mut self.details),
+            "neighborhood" => Some(&mut self.neighborhood),
+            "state" => Some(&mut self.state),
+            "city" => Some(&mut self.city),
+            "ibge" => Some(&mut self.ibge),
+            "gia" => Some(&mut self.gia),
+            "unidade" => Some(&mut self.unidade),
+            _ => None,
+        }
+    }
+}
+
+/// normalize_cep keeps only the digits of a CEP, so that "70150-903" and "70150903" are the same
+pub fn normalize_cep(cep: &str) -> String {
+    cep.chars().filter(|c| c.is_ascii_digit()).collect()
+}
+
+/// check_status maps an unsuccessful response status to an error.
+/// 429 and 503 responses with a Retry-After header in seconds are reported as Throttled, so the wait can be honoured.
+pub(crate) fn check_status<T>(response: &Response<T>, source: Source) -> Result<(), Error> {
+    let code = response.status().as_u16();
+    let retry_after = || {
+        response
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_secs)
+    };
+    let kind = match code {
+        200..=299 => return Ok(()),
+        429 | 503 => match retry_after() {
+            Some(retry_after) => Kind::Throttled { code, retry_after },
+            None if code == 429 => Kind::ClientError { code },
+            None => Kind::ServerError { code },
+        },
+        400..=499 => Kind::ClientError { code },
+        500..=599 => Kind::ServerError { code },
+        _ => Kind::UnknownServerError { code },
+    };
+    Err(Error { source, kind })
+}
+
+/// transport_error maps a request that failed without an answer to a Network, Tls or NetworkTimeout error, keeping its cause
+pub(crate) fn transport_error(error: isahc::Error, source: Source) -> Error {
+    let kind = if error.is_timeout() {
+        Kind::NetworkTimeout {
+            cause: Cause::new(error),
+        }
+    } else if error.is_tls() {
+        Kind::Tls {
+            cause: Cause::new(error),
+        }
+    } else {
+        Kind::Network {
+            cause: Cause::new(error),
+        }
+    };
+    Error { source, kind }
 }
 
 pub trait Addressable {
@@ -21,48 +130,130 @@ pub trait Addressable {
     fn to_address(&self) -> Address;
 }
 
+/// Capabilities describes which optional fields of the unified Address a provider is able to fill
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// details indicates that the provider returns the address complement
+    pub details: bool,
+    /// ibge indicates that the provider returns the IBGE city code
+    pub ibge: bool,
+    /// gia indicates that the provider returns the GIA code
+    pub gia: bool,
+    /// unidade indicates that the provider returns the unit name
+    pub unidade: bool,
+}
+
+impl Capabilities {
+    /// supports indicates whether the provider fills the named Address field.
+    /// Every provider is expected to fill the fields that are not optional.
+    pub fn supports(&self, field: &str) -> bool {
+        match field {
+            "details" => self.details,
+            "ibge" => self.ibge,
+            "gia" => self.gia,
+            "unidade" => self.unidade,
+            _ => true,
+        }
+    }
+}
+
+/// CepProvider is implemented by every service that can be added to the pool of a Lagoinha client.
+/// The built-in services implement it, and custom backends can implement it to join the race.
+///
+/// # Example
+/// ```
+///use futures::future::{BoxFuture, FutureExt};
+///use lagoinha::error::Error;
+///use lagoinha::services::{Address, CepProvider};
+///
+///struct Database;
+///
+///impl CepProvider for Database {
+///    fn name(&self) -> &str {
+///        "database"
+///    }
+///
+///    fn lookup<'a>(&'a self, cep: &'a str) -> BoxFuture<'a, Result<Address, Error>> {
+///        async move {
+///            Ok(Address {
+///                cep: cep.to_owned(),
+///                address: "SPP".to_owned(),
+///                details: "".to_owned(),
+///                neighborhood: "Zona Cívico-Administrativa".to_owned(),
+///                state: "DF".to_owned(),
+///                city: "Brasília".to_owned(),
+///                ..Default::default()
+///            })
+///        }
+///        .boxed()
+///    }
+///}
+///
+///let client = lagoinha::Lagoinha::builder().provider(Database).build();
+///```
+pub trait CepProvider: Send + Sync {
+    /// name identifies the provider in the pool
+    fn name(&self) -> &str;
+
+    /// source is the error::Source used in errors raised on behalf of this provider. By default, Custom with its name.
+    fn source(&self) -> Source {
+        Source::Custom(self.name().to_owned())
+    }
+
+    /// lookup requests the address related to the provided `cep`, converted to the unified Address
+    fn lookup<'a>(&'a self, cep: &'a str) -> BoxFuture<'a, Result<Address, Error>>;
+
+    /// capabilities informs which optional fields this provider fills. By default, none.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+}
+
 impl Addressable for viacep::Address {
     /// to_address implementtion converts services::viacep::Address to services::Address
     fn to_address(&self) -> Address {
-        let addr = Address {
+        Address {
             cep: self.cep.clone(),
             address: self.address.clone(),
             details: self.details.clone(),
             neighborhood: self.neighborhood.clone(),
             state: self.state.clone(),
             city: self.city.clone(),
-        };
-        addr
+            ibge: self.ibge.clone(),
+            gia: self.gia.clone(),
+            unidade: self.unidade.clone(),
+        }
     }
 }
 
 impl Addressable for correios::Address {
     /// to_address implementtion converts services::correios::Address to services::Address
     fn to_address(&self) -> Address {
-        let addr = Address {
+        Address {
             cep: self.cep.clone(),
             address: self.address.clone(),
             details: "".to_string(),
             neighborhood: self.neighborhood.clone(),
             state: self.state.clone(),
             city: self.city.clone(),
-        };
-        addr
+            ..Default::default()
+        }
     }
 }
 
 impl Addressable for cepla::Address {
     /// to_address implementtion converts services::cepla::Address to services::Address
     fn to_address(&self) -> Address {
-        let addr = Address {
+        Address {
             cep: self.cep.clone(),
             address: self.address.clone(),
             details: self.details.clone(),
             neighborhood: self.neighborhood.clone(),
             state: self.state.clone(),
             city: self.city.clone(),
-        };
-        addr
+            ..Default::default()
+        }
     }
 }
 
@@ -73,6 +264,81 @@ mod tests {
     use super::viacep;
     use super::Addressable;
 
+    #[test]
+    fn normalize_cep() {
+        assert_eq!(super::normalize_cep("70150-903"), "70150903");
+        assert_eq!(super::normalize_cep(" 70150903 "), "70150903");
+    }
+
+    #[test]
+    fn check_status() {
+        use crate::error::{Kind, Source};
+        use isahc::http::Response;
+        use std::time::Duration;
+
+        let status = |code: u16, retry_after: Option<&str>| {
+            let mut response = Response::builder().status(code);
+            if let Some(value) = retry_after {
+                response = response.header("Retry-After", value);
+            }
+            super::check_status(&response.body(()).unwrap(), Source::Viacep).map_err(|e| e.kind)
+        };
+
+        assert_eq!(status(200, None), Ok(()));
+        assert_eq!(status(404, None), Err(Kind::ClientError { code: 404 }));
+        assert_eq!(status(429, None), Err(Kind::ClientError { code: 429 }));
+        assert_eq!(status(500, None), Err(Kind::ServerError { code: 500 }));
+        assert_eq!(
+            status(302, None),
+            Err(Kind::UnknownServerError { code: 302 })
+        );
+        assert_eq!(
+            status(503, Some("3")),
+            Err(Kind::Throttled {
+                code: 503,
+                retry_after: Duration::from_secs(3)
+            })
+        );
+        // only delays in seconds are understood
+        assert_eq!(
+            status(503, Some("Wed, 21 Oct 2015 07:28:00 GMT")),
+            Err(Kind::ServerError { code: 503 })
+        );
+    }
+
+    #[test]
+    fn transport_errors_keep_their_cause() {
+        use crate::error::{Kind, Source};
+        use isahc::error::ErrorKind;
+        use std::error::Error as _;
+
+        let error = |kind: ErrorKind| super::transport_error(kind.into(), Source::Cepla);
+
+        let err = error(ErrorKind::ConnectionFailed);
+        assert!(matches!(err.kind, Kind::Network { .. }));
+        let cause = err
+            .source()
+            .unwrap()
+            .downcast_ref::<isahc::Error>()
+            .unwrap();
+        assert_eq!(cause.kind(), &ErrorKind::ConnectionFailed);
+        assert!(err.to_string().contains("Cepla"));
+
+        assert!(matches!(
+            error(ErrorKind::BadServerCertificate).kind,
+            Kind::Tls { .. }
+        ));
+        assert!(matches!(
+            error(ErrorKind::Timeout).kind,
+            Kind::NetworkTimeout { .. }
+        ));
+        assert_eq!(error(ErrorKind::Timeout), error(ErrorKind::Timeout));
+        assert_ne!(error(ErrorKind::Timeout), error(ErrorKind::NameResolution));
+
+        // errors without a cause end the chain
+        assert!(error(ErrorKind::Io).source().unwrap().source().is_none());
+    }
+
     #[test]
     fn viacep_conversion() {
         let viac_addr = viacep::Address {
@@ -97,6 +363,7 @@ mod tests {
             address: "SPP".to_string(),
             details: "Palácio da Alvorada (Residência Oficial do Presidente da República)"
                 .to_string(),
+            ..Default::default()
         };
 
         assert_eq!(addr.address, viac_addr.address);
@@ -105,6 +372,7 @@ mod tests {
         assert_eq!(addr.city, viac_addr.city);
         assert_eq!(addr.cep, viac_addr.cep);
         assert_eq!(addr.details, viac_addr.details);
+        assert_eq!("5300108", viac_addr.ibge);
     }
 
     #[test]
@@ -126,6 +394,7 @@ mod tests {
             address: "SPP".to_string(),
             details: "Palácio da Alvorada (Residência Oficial do Presidente da República)"
                 .to_string(),
+            ..Default::default()
         };
 
         assert_eq!(addr.address, corr_addr.address);
@@ -156,6 +425,7 @@ mod tests {
             address: "SPP".to_string(),
             details: "Palácio da Alvorada (Residência Oficial do Presidente da República)"
                 .to_string(),
+            ..Default::default()
         };
 
         assert_eq!(addr.address, cepl_addr.address);
@@ -2,11 +2,15 @@
 
 use crate::error::Error;
 use crate::error::Kind;
+use crate::error::Source;
 use crate::error::Source::Viacep;
 
+use crate::services::{self, Addressable, Capabilities, CepProvider};
+
+use futures::future::{BoxFuture, FutureExt};
 use serde::{Deserialize, Serialize};
 
-use isahc::{ReadResponseExt, Request, RequestExt};
+use isahc::{AsyncReadResponseExt, Request, RequestExt};
 
 /// request function runs the API call to Viacep service
 pub async fn request(cep: &str) -> Result<Address, Error> {
@@ -19,57 +23,75 @@ pub async fn request(cep: &str) -> Result<Address, Error> {
             source: Viacep,
         }))?;
 
-    let mut response = req.send().or(Err(Error {
+    let mut response = req
+        .send_async()
+        .await
+        .map_err(|e| services::transport_error(e, Viacep))?;
+
+    services::check_status(&response, Viacep)?;
+
+    let body = response.text().await.or(Err(Error {
         kind: Kind::MissingBodyError,
         source: Viacep,
     }))?;
 
-    match response.status().as_u16() {
-        200..=299 => (),
-        400..=499 => {
-            return Err(Error {
-                kind: Kind::ClientError {
-                    code: response.status().as_u16(),
-                },
-                source: Viacep,
-            });
-        }
-        500..=599 => {
-            return Err(Error {
-                kind: Kind::ServerError {
-                    code: response.status().as_u16(),
-                },
-                source: Viacep,
-            });
-        }
-        _ => {
-            return Err(Error {
-                kind: Kind::UnknownServerError {
-                    code: response.status().as_u16(),
-                },
-                source: Viacep,
-            });
-        }
+    if not_found(&body) {
+        return Err(Error {
+            kind: Kind::NotFound,
+            source: Viacep,
+        });
     }
-    let body = response.body_mut();
-    let address = serde_json::from_reader(body);
+
+    let address = serde_json::from_str(&body);
     match address {
-        Ok(address) => return Ok(address),
-        Err(e) => {
-            let str_body = response.text();
-            let str_body = match str_body {
-                Ok(str_body) => str_body,
-                Err(_) => "Failed to produce string body ".to_owned() + e.to_string().as_str(),
-            };
-            return Err(Error {
-                kind: Kind::BodyParsingError {
-                    error: e.to_string(),
-                    body: str_body.to_string(),
-                },
-                source: Viacep,
-            });
+        Ok(address) => Ok(address),
+        Err(e) => Err(Error {
+            kind: Kind::BodyParsingError {
+                error: e.to_string(),
+                body,
+            },
+            source: Viacep,
+        }),
+    }
+}
+
+/// not_found indicates whether the body is the answer Viacep gives to a CEP that does not exist: {"erro": true}.
+/// The flag sometimes comes as a string.
+fn not_found(body: &str) -> bool {
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(value) => {
+            matches!(value.get("erro"), Some(serde_json::Value::Bool(true)))
+                || value.get("erro").and_then(|erro| erro.as_str()) == Some("true")
         }
-    };
+        Err(_) => false,
+    }
+}
+
+/// ViacepProvider is the Viacep service as a CepProvider, so it can be added to the pool of a Lagoinha client
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ViacepProvider;
+
+impl CepProvider for ViacepProvider {
+    fn name(&self) -> &str {
+        "viacep"
+    }
+
+    fn source(&self) -> Source {
+        Viacep
+    }
+
+    fn lookup<'a>(&'a self, cep: &'a str) -> BoxFuture<'a, Result<services::Address, Error>> {
+        async move { request(cep).await.map(|addr| addr.to_address()) }.boxed()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            details: true,
+            ibge: true,
+            gia: true,
+            unidade: true,
+        }
+    }
 }
 
 /// Address struct used to deserialize the results from the viacep API
@@ -97,6 +119,14 @@ pub struct Address {
 
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn not_found_body() {
+        assert!(super::not_found(r#"{"erro": true}"#));
+        assert!(super::not_found(r#"{"erro": "true"}"#));
+        assert!(!super::not_found(r#"{"cep": "70150-903", "uf": "DF"}"#));
+        assert!(!super::not_found("not json"));
+    }
+
     #[test]
     fn valid_viacep() {
         let resaddr = async_std::task::block_on(super::request("70150903")).unwrap();
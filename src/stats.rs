@@ -0,0 +1,184 @@
+//! Stats keeps rolling statistics of the calls made to each provider, to rank them and to feed dashboards.
+//!
+//! # Example
+//! ```
+//!extern crate lagoinha;
+//!extern crate tokio;
+//!
+//!use lagoinha::client::Strategy;
+//!
+//!#[tokio::main]
+//!async fn main() {
+//!    let client = lagoinha::Lagoinha::builder()
+//!        .strategy(Strategy::Fallback { hedge: None })
+//!        .adaptive_ranking(true)
+//!        .build();
+//!    let _ = client.get_address("70150903").await;
+//!    for stats in client.stats() {
+//!        println!("{}: p50 {:?}, {:.0}% errors", stats.provider, stats.p50, stats.error_rate * 100.0);
+//!    }
+//!}
+//!```
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// STATS_WINDOW is the number of most recent calls kept for each provider
+pub const STATS_WINDOW: usize = 100;
+
+/// ProviderStats summarizes the most recent calls to a provider
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderStats {
+    pub provider: String,
+    /// calls is the number of calls in the window, up to STATS_WINDOW
+    pub calls: usize,
+    /// error_rate is the share of the calls in the window that failed because of the provider, from 0 to 1
+    pub error_rate: f64,
+    /// p50 is the median latency of the successful calls, or None if there was none
+    pub p50: Option<Duration>,
+    /// p90 is the 90th percentile latency of the successful calls
+    pub p90: Option<Duration>,
+    /// p99 is the 99th percentile latency of the successful calls
+    pub p99: Option<Duration>,
+}
+
+impl ProviderStats {
+    /// score is the expected cost of calling the provider: its median latency, inflated by its error rate.
+    /// Lower is better. It is None while there are no calls in the window.
+    pub fn score(&self) -> Option<f64> {
+        if self.calls == 0 {
+            return None;
+        }
+        match self.p50 {
+            Some(p50) => Some(p50.as_secs_f64() / (1.0 - self.error_rate).max(0.01)),
+            None => Some(f64::INFINITY),
+        }
+    }
+}
+
+struct Sample {
+    latency: Duration,
+    failed: bool,
+}
+
+/// Stats holds the window of recent calls to a single provider
+#[derive(Default)]
+pub(crate) struct Stats {
+    samples: Mutex<VecDeque<Sample>>,
+}
+
+impl Stats {
+    /// record adds a call to the window, dropping the oldest one when it is full
+    pub(crate) fn record(&self, latency: Duration, failed: bool) {
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() == STATS_WINDOW {
+            samples.pop_front();
+        }
+        samples.push_back(Sample { latency, failed });
+    }
+
+    pub(crate) fn snapshot(&self, provider: &str) -> ProviderStats {
+        let samples = self.samples.lock().unwrap();
+        let failures = samples.iter().filter(|s| s.failed).count();
+        let mut latencies: Vec<Duration> = samples
+            .iter()
+            .filter(|s| !s.failed)
+            .map(|s| s.latency)
+            .collect();
+        latencies.sort();
+
+        // nearest rank percentile
+        let percentile = |p: usize| {
+            let rank = (latencies.len() * p).div_ceil(100);
+            latencies.get(rank.max(1) - 1).copied()
+        };
+        ProviderStats {
+            provider: provider.to_owned(),
+            calls: samples.len(),
+            error_rate: match samples.len() {
+                0 => 0.0,
+                calls => failures as f64 / calls as f64,
+            },
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        }
+    }
+}
+
+/// rank returns the positions of the given stats, best first.
+/// Providers without calls come first, so that they are tried and measured, and ties keep their original order.
+pub(crate) fn rank(stats: &[ProviderStats]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..stats.len()).collect();
+    order.sort_by(|&a, &b| {
+        let score = |i: usize| stats[i].score().unwrap_or(0.0);
+        score(a).total_cmp(&score(b))
+    });
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rank, Stats, STATS_WINDOW};
+    use std::time::Duration;
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn percentiles_and_error_rate() {
+        let stats = Stats::default();
+        for ms in 1..=100 {
+            stats.record(millis(ms), false);
+        }
+        let snapshot = stats.snapshot("viacep");
+        assert_eq!(snapshot.provider, "viacep");
+        assert_eq!(snapshot.calls, 100);
+        assert_eq!(snapshot.error_rate, 0.0);
+        assert_eq!(snapshot.p50, Some(millis(50)));
+        assert_eq!(snapshot.p90, Some(millis(90)));
+        assert_eq!(snapshot.p99, Some(millis(99)));
+
+        // failures push out the oldest calls, and do not count in the latencies
+        for _ in 0..50 {
+            stats.record(millis(5_000), true);
+        }
+        let snapshot = stats.snapshot("viacep");
+        assert_eq!(snapshot.calls, STATS_WINDOW);
+        assert_eq!(snapshot.error_rate, 0.5);
+        assert_eq!(snapshot.p50, Some(millis(75)));
+    }
+
+    #[test]
+    fn empty_stats() {
+        let snapshot = Stats::default().snapshot("viacep");
+        assert_eq!(snapshot.calls, 0);
+        assert_eq!(snapshot.p50, None);
+        assert_eq!(snapshot.score(), None);
+    }
+
+    #[test]
+    fn rank_by_score() {
+        let record = |samples: &[(u64, bool)]| {
+            let stats = Stats::default();
+            for &(ms, failed) in samples {
+                stats.record(millis(ms), failed);
+            }
+            stats
+        };
+        let slow = record(&[(300, false)]);
+        let fast = record(&[(100, false)]);
+        // faster, but fails most of the time
+        let flaky = record(&[(50, false), (50, true), (50, true), (50, true)]);
+        let broken = record(&[(10, true)]);
+        let unknown = record(&[]);
+
+        let snapshots: Vec<_> = [slow, fast, flaky, broken, unknown]
+            .iter()
+            .map(|stats| stats.snapshot(""))
+            .collect();
+        assert_eq!(rank(&snapshots), vec![4, 1, 2, 0, 3]);
+    }
+}